@@ -0,0 +1,258 @@
+use std::time::Duration;
+
+use tokio::time::sleep;
+
+use crate::{config::Config, database::Database, error::Error, gazetteer, telegram_bot::{escape_html, TelegramBot, TelegramParseMode}};
+
+const POLLING_TIMEOUT: Duration = Duration::from_secs(50);
+const POLLING_ERROR_DELAY: Duration = Duration::from_secs(5);
+const LATEST_POSTS_COUNT: usize = 5;
+// NOTE: How many of the newest posts are searched for the ones matching the
+// chat's subscriptions.
+const LATEST_POSTS_SCANNED: usize = 200;
+const SEARCH_RESULTS_COUNT: usize = 5;
+
+const HELP_MESSAGE: &str = "<b>Comandos disponíveis</b>
+
+/subscribe - receber todos os comunicados
+/subscribe <i>bairro</i> - receber apenas comunicados que mencionem o bairro ou município
+/unsubscribe - parar de receber comunicados
+/unsubscribe <i>bairro</i> - remover um bairro ou município
+/providers - listar as concessionárias acompanhadas
+/latest - ver os últimos comunicados
+/buscar <i>termo</i> - procurar nos comunicados já publicados
+/help - mostrar esta mensagem";
+
+#[derive(Debug, PartialEq, Eq)]
+enum Command<'a> {
+    Start,
+    Help,
+    Subscribe(Option<&'a str>),
+    Unsubscribe(Option<&'a str>),
+    Providers,
+    Latest,
+    Search(Option<&'a str>),
+}
+
+impl<'a> Command<'a> {
+    // NOTE: Commands sent in groups may be addressed to a specific bot, as
+    // in "/subscribe@SomeBot Bangu", so the suffix is ignored.
+    fn parse(text: &'a str) -> Option<Self> {
+        let text = text.trim();
+        let (command, argument) = text.split_once(char::is_whitespace).unwrap_or((text, ""));
+        let command = command.split('@').next()?;
+        let argument = Some(argument.trim()).filter(|argument| !argument.is_empty());
+
+        match command {
+            "/start" => Some(Command::Start),
+            "/help" => Some(Command::Help),
+            "/subscribe" => Some(Command::Subscribe(argument)),
+            "/unsubscribe" => Some(Command::Unsubscribe(argument)),
+            "/providers" => Some(Command::Providers),
+            "/latest" => Some(Command::Latest),
+            "/buscar" => Some(Command::Search(argument)),
+            _ => None,
+        }
+    }
+}
+
+pub struct CommandHandler<'a> {
+    bot: &'a TelegramBot,
+    database: &'a Database,
+    config: &'a Config,
+}
+
+impl<'a> CommandHandler<'a> {
+    pub fn new(bot: &'a TelegramBot, database: &'a Database, config: &'a Config) -> Self {
+        Self {
+            bot,
+            database,
+            config,
+        }
+    }
+
+    // NOTE: Polls Telegram forever. Errors are logged and do not stop the
+    // loop, since a single bad update or network hiccup should not take the
+    // bot offline.
+    pub async fn run_update_loop(&self) {
+        let mut offset = None;
+
+        loop {
+            let updates = match self.bot.get_updates(offset, POLLING_TIMEOUT).await {
+                Ok(updates) => updates,
+                Err(error) => {
+                    eprintln!("Could not get updates: {}", error);
+                    sleep(POLLING_ERROR_DELAY).await;
+                    continue;
+                },
+            };
+
+            for update in updates {
+                offset = Some(update.update_id + 1);
+
+                let Some(message) = update.message else {
+                    continue;
+                };
+                let Some(text) = message.text else {
+                    continue;
+                };
+
+                if let Err(error) = self.handle_message(&text, &message.chat.id.to_string()).await {
+                    eprintln!("Could not handle \"{}\": {}", text, error);
+                }
+            }
+        }
+    }
+
+    pub async fn handle_message(&self, text: &str, chat_id: &str) -> Result<(), Error> {
+        let Some(command) = Command::parse(text) else {
+            return Ok(());
+        };
+
+        let reply = match command {
+            Command::Start => format!("Olá! Eu envio os comunicados das concessionárias de água e esgoto do Rio de Janeiro.\n\n{}", HELP_MESSAGE),
+            Command::Help => HELP_MESSAGE.to_string(),
+            Command::Subscribe(place) => self.subscribe(chat_id, place)?,
+            Command::Unsubscribe(place) => self.unsubscribe(chat_id, place)?,
+            Command::Providers => self.providers(chat_id)?,
+            Command::Latest => self.latest(chat_id)?,
+            Command::Search(query) => self.search(query)?,
+        };
+
+        self.bot.send_message(&reply, chat_id, TelegramParseMode::Html).await?;
+
+        Ok(())
+    }
+
+    fn subscribe(&self, chat_id: &str, place: Option<&str>) -> Result<String, Error> {
+        let neighbourhood = match place {
+            Some(place) => match gazetteer::canonical_name(place) {
+                Some(neighbourhood) => Some(neighbourhood),
+                None => return Ok(format!("Não conheço \"{}\". Confira a grafia do bairro ou município.", escape_html(place))),
+            },
+            None => None,
+        };
+
+        for (provider, enabled) in self.config.scrapers.providers() {
+            if enabled {
+                self.database.add_route(provider, chat_id)?;
+            }
+        }
+
+        if let Some(neighbourhood) = neighbourhood {
+            self.database.add_neighbourhood(chat_id, neighbourhood)?;
+        }
+
+        let neighbourhoods = self.database.neighbourhoods_for_chat(chat_id)?;
+        if neighbourhoods.is_empty() {
+            return Ok("Pronto! Você vai receber todos os comunicados.".to_string());
+        }
+
+        Ok(format!("Pronto! Você vai receber os comunicados que mencionem: {}.", neighbourhoods.join(", ")))
+    }
+
+    fn unsubscribe(&self, chat_id: &str, place: Option<&str>) -> Result<String, Error> {
+        let Some(place) = place else {
+            self.database.remove_chat_subscriptions(chat_id)?;
+            return Ok("Você não vai mais receber comunicados.".to_string());
+        };
+
+        let removed = match gazetteer::canonical_name(place) {
+            Some(neighbourhood) => self.database.remove_neighbourhood(chat_id, neighbourhood)?,
+            None => false,
+        };
+
+        if !removed {
+            return Ok(format!("Você não acompanha \"{}\".", escape_html(place)));
+        }
+
+        // NOTE: A chat without neighbourhoods receives every post, so
+        // removing the last one would flood it instead of silencing it.
+        let neighbourhoods = self.database.neighbourhoods_for_chat(chat_id)?;
+        if neighbourhoods.is_empty() {
+            self.database.remove_chat_subscriptions(chat_id)?;
+            return Ok("Você não acompanha mais nenhum bairro e não vai mais receber comunicados.".to_string());
+        }
+
+        Ok(format!("Pronto! Você continua recebendo os comunicados que mencionem: {}.", neighbourhoods.join(", ")))
+    }
+
+    fn providers(&self, chat_id: &str) -> Result<String, Error> {
+        let subscribed_providers = self.database.providers_for_chat(chat_id)?;
+
+        let mut reply = "<b>Concessionárias acompanhadas</b>\n".to_string();
+        for (provider, enabled) in self.config.scrapers.providers() {
+            if !enabled {
+                continue;
+            }
+
+            let mark = if subscribed_providers.iter().any(|subscribed| subscribed == provider) { "✅" } else { "▫️" };
+            reply.push_str(&format!("\n{} {}", mark, escape_html(provider)));
+        }
+
+        Ok(reply)
+    }
+
+    fn latest(&self, chat_id: &str) -> Result<String, Error> {
+        let subscribed_providers = self.database.providers_for_chat(chat_id)?;
+        let neighbourhoods = self.database.neighbourhoods_for_chat(chat_id)?;
+
+        let posts = self.database.latest_posts(LATEST_POSTS_SCANNED)?;
+
+        // NOTE: Chats without subscriptions see the latest posts of every
+        // provider, so /latest is useful before subscribing.
+        let latest_posts = posts
+            .iter()
+            .filter(|post| subscribed_providers.is_empty() || subscribed_providers.iter().any(|provider| provider == post.provider()))
+            .filter(|post| neighbourhoods.is_empty() || post.places().mentions_any(&neighbourhoods))
+            .take(LATEST_POSTS_COUNT)
+            .collect::<Vec<_>>();
+
+        if latest_posts.is_empty() {
+            return Ok("Nenhum comunicado encontrado.".to_string());
+        }
+
+        let mut reply = "<b>Últimos comunicados</b>\n".to_string();
+        for post in latest_posts {
+            reply.push_str(&format!("\n• {} ({})", post.as_html_link(), escape_html(post.provider())));
+        }
+
+        Ok(reply)
+    }
+
+    // NOTE: Searches every stored post, regardless of the chat's
+    // subscriptions.
+    fn search(&self, query: Option<&str>) -> Result<String, Error> {
+        let Some(query) = query else {
+            return Ok("Use /buscar <i>termo</i>, por exemplo: /buscar falta d'água Bangu".to_string());
+        };
+
+        let posts = self.database.search_posts(query, SEARCH_RESULTS_COUNT)?;
+        if posts.is_empty() {
+            return Ok(format!("Nenhum comunicado encontrado para \"{}\".", escape_html(query)));
+        }
+
+        let mut reply = format!("<b>Comunicados sobre \"{}\"</b>\n", escape_html(query));
+        for post in posts {
+            reply.push_str(&format!("\n• {} ({})", post.as_html_link(), escape_html(post.provider())));
+        }
+
+        Ok(reply)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Command;
+
+    #[test]
+    fn parses_commands() {
+        assert_eq!(Command::parse("/start"), Some(Command::Start));
+        assert_eq!(Command::parse("/subscribe"), Some(Command::Subscribe(None)));
+        assert_eq!(Command::parse("/subscribe  Campo Grande "), Some(Command::Subscribe(Some("Campo Grande"))));
+        assert_eq!(Command::parse("/unsubscribe@ComunicadosBot Bangu"), Some(Command::Unsubscribe(Some("Bangu"))));
+        assert_eq!(Command::parse("/latest@ComunicadosBot"), Some(Command::Latest));
+        assert_eq!(Command::parse("/buscar água  Bangu"), Some(Command::Search(Some("água  Bangu"))));
+        assert_eq!(Command::parse("bom dia"), None);
+    }
+}
@@ -0,0 +1,137 @@
+use std::path::PathBuf;
+
+use chrono::NaiveDate;
+use clap::{Parser, Subcommand};
+
+#[derive(Debug, Parser)]
+#[command(version, about = "Sends the notices of Rio de Janeiro's water and sewage providers to Telegram")]
+#[command(args_conflicts_with_subcommands = true)]
+pub struct Cli {
+    // NOTE: Kept so deployments started with "--daemon" keep working.
+    #[arg(long, hide = true)]
+    daemon: bool,
+
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Debug, PartialEq, Eq, Subcommand)]
+pub enum Command {
+    #[command(about = "Scrape every provider once and deliver the new posts (default)")]
+    Run,
+    #[command(about = "Scrape on a schedule and answer bot commands until stopped")]
+    Daemon,
+    #[command(about = "Only answer bot commands")]
+    Listen,
+    #[command(about = "Scrape every provider and print the messages that would be sent, without sending or saving anything")]
+    DryRun {
+        #[arg(long, help = "Write every message part to a file in this directory instead of printing it")]
+        output: Option<PathBuf>,
+    },
+    #[command(about = "Scrape every provider and mark the posts listed as delivered, without sending them")]
+    Seed,
+    #[command(about = "Read older pages of the providers and store their posts without sending them")]
+    Backfill {
+        #[arg(long, help = "Only backfill this provider")]
+        provider: Option<String>,
+        #[arg(long, default_value_t = 10, help = "How many pages to read from each provider, after those read on every run")]
+        pages: usize,
+        #[arg(long, help = "Stop at posts published before this date (YYYY-MM-DD)")]
+        until: Option<NaiveDate>,
+    },
+    #[command(about = "Scrape a single provider and print its posts as JSON")]
+    Scrape {
+        provider: String,
+    },
+    #[command(about = "Scrape every provider and save the pages fetched as fixtures")]
+    Record {
+        directory: PathBuf,
+    },
+    #[command(about = "Like dry-run, but using recorded fixtures instead of the network")]
+    Replay {
+        directory: PathBuf,
+        #[arg(long, help = "Write every message part to a file in this directory instead of printing it")]
+        output: Option<PathBuf>,
+    },
+    #[command(about = "Search the stored posts")]
+    Search {
+        #[arg(required = true)]
+        terms: Vec<String>,
+    },
+    #[command(about = "Inspect the stored posts")]
+    Db {
+        #[command(subcommand)]
+        command: DbCommand,
+    },
+    #[command(about = "Send a stored post again to every chat subscribed to its provider")]
+    Resend {
+        id: String,
+    },
+    #[command(about = "Inspect the configuration")]
+    Config {
+        #[command(subcommand)]
+        command: ConfigCommand,
+    },
+}
+
+#[derive(Debug, PartialEq, Eq, Subcommand)]
+pub enum DbCommand {
+    #[command(about = "List the newest stored posts")]
+    List {
+        #[arg(long, default_value_t = 20)]
+        limit: usize,
+    },
+    #[command(about = "Show a stored post and where it was delivered")]
+    Show {
+        id: String,
+    },
+    #[command(about = "Forget a post, so it is delivered again as new on the next run")]
+    Forget {
+        id: String,
+    },
+}
+
+#[derive(Debug, PartialEq, Eq, Subcommand)]
+pub enum ConfigCommand {
+    #[command(about = "Validate the configuration and show the routes it defines")]
+    Check,
+}
+
+impl Cli {
+    pub fn into_command(self) -> Command {
+        match self.command {
+            Some(command) => command,
+            None if self.daemon => Command::Daemon,
+            None => Command::Run,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use chrono::NaiveDate;
+    use clap::{CommandFactory, Parser};
+
+    use super::{Cli, Command, DbCommand};
+
+    fn parse(args: &[&str]) -> Command {
+        Cli::try_parse_from([&["comunicados-aguas-do-rio-rust"], args].concat()).unwrap().into_command()
+    }
+
+    #[test]
+    fn parses_commands() {
+        Cli::command().debug_assert();
+
+        assert_eq!(parse(&[]), Command::Run);
+        assert_eq!(parse(&["--daemon"]), Command::Daemon);
+        assert_eq!(parse(&["replay", "tests/fixtures"]), Command::Replay { directory: PathBuf::from("tests/fixtures"), output: None });
+        assert_eq!(parse(&["search", "falta", "d'água"]), Command::Search { terms: vec!["falta".to_string(), "d'água".to_string()] });
+        assert_eq!(parse(&["db", "list"]), Command::Db { command: DbCommand::List { limit: 20 } });
+        assert_eq!(parse(&["backfill", "--until", "2024-10-01"]), Command::Backfill { provider: None, pages: 10, until: NaiveDate::from_ymd_opt(2024, 10, 1) });
+
+        assert!(Cli::try_parse_from(["comunicados-aguas-do-rio-rust", "--daemon", "listen"]).is_err());
+        assert!(Cli::try_parse_from(["comunicados-aguas-do-rio-rust", "search"]).is_err());
+    }
+}
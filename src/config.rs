@@ -0,0 +1,782 @@
+use std::{env, fs, path::{Path, PathBuf}, str::FromStr, time::Duration};
+
+use chrono::{format::{Item, StrftimeItems}, NaiveDate, NaiveDateTime};
+use reqwest::Url;
+use scraper::Selector;
+use serde::Deserialize;
+
+use crate::{error::Error, gazetteer, http_client::HttpClientConfig, scrapers::{aguas_do_rio_scraper, cedae_scraper, igua_scraper, rio_saneamento_scraper}, telegram_bot::TelegramBotConfig};
+
+const DEFAULT_CONFIG_PATH: &str = "./config.toml";
+
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub telegram: TelegramConfig,
+    pub database: DatabaseConfig,
+    pub delivery: DeliveryConfig,
+    pub daemon: DaemonConfig,
+    pub http: HttpConfig,
+    pub scrapers: ScrapersConfig,
+    pub destinations: Vec<DestinationConfig>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct TelegramConfig {
+    pub api_key: String,
+    pub chat_id: String,
+    pub owner_chat_id: String,
+}
+
+// NOTE: Maps a chat to the providers it wants to receive posts from. When
+// no destination is configured, every provider is sent to telegram.chat_id.
+// A chat with neighbourhoods only receives posts mentioning one of them
+// (municipality names are accepted too).
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DestinationConfig {
+    pub chat_id: String,
+    pub providers: Vec<String>,
+    #[serde(default)]
+    pub neighbourhoods: Vec<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DatabaseConfig {
+    pub path: PathBuf,
+}
+
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DeliveryConfig {
+    // NOTE: Minimum interval between messages to the same chat.
+    pub messages_interval_ms: u64,
+    pub global_messages_per_second: u32,
+    // NOTE: Retries of network errors, server errors and rate limited
+    // requests, per message.
+    pub max_retries: u32,
+    // NOTE: Runs in which an undelivered message is tried again before it
+    // is given up on.
+    pub max_attempts: u32,
+    pub on_update: UpdateMode,
+    // NOTE: While a provider has no stored posts, the posts it currently
+    // lists are marked as delivered instead of sent, so a new deployment does
+    // not flood its chats with old notices.
+    pub seed_on_first_run: bool,
+    // NOTE: Dated posts older than this are not sent to chats that have not
+    // received them yet. Edits of delivered posts are still sent.
+    pub max_age_days: Option<u32>,
+}
+
+// NOTE: What to do when a post that was already delivered is edited by its
+// provider. Editing keeps the chat tidy, but Telegram does not notify anyone
+// about edited messages.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum UpdateMode {
+    Edit,
+    New,
+    Both,
+}
+
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DaemonConfig {
+    // NOTE: Used by scrapers without their own interval_secs.
+    pub default_interval_secs: u64,
+    // NOTE: A random delay of up to this many seconds is added to every
+    // interval, so requests do not always hit the providers at the same time.
+    pub jitter_secs: u64,
+}
+
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HttpConfig {
+    pub user_agent: String,
+    pub proxy: Option<String>,
+
+    pub connect_timeout_secs: u64,
+    pub read_timeout_secs: u64,
+
+    pub max_retries: u32,
+    pub max_requests_per_host: usize,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct ScrapersConfig {
+    pub cedae: ScraperConfig,
+    pub rio_saneamento: ScraperConfig,
+    pub igua: ScraperConfig,
+    pub aguas_do_rio: ScraperConfig,
+
+    pub generic: Vec<GenericScraperConfig>,
+}
+
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ScraperConfig {
+    pub enabled: bool,
+    // NOTE: When not set, each scraper falls back to its own default URL.
+    pub url: Option<String>,
+    pub interval_secs: Option<u64>,
+    // NOTE: How many pages of the news list are read on every run, so posts
+    // are not missed when a provider publishes more than a page between runs.
+    pub pages: usize,
+}
+
+// NOTE: Describes a provider whose news list can be scraped with CSS
+// selectors only, so it can be added without writing a new scraper.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct GenericScraperConfig {
+    pub name: String,
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+    pub url: String,
+
+    pub posts_selector: String,
+    pub title_selector: String,
+    pub link_selector: Option<String>,
+    pub date_selector: Option<String>,
+    pub content_selector: Option<String>,
+
+    #[serde(default = "default_date_format")]
+    pub date_format: String,
+    #[serde(default)]
+    pub follow_links: bool,
+    pub detail_content_selector: Option<String>,
+
+    pub interval_secs: Option<u64>,
+    #[serde(default = "default_pages")]
+    pub pages: usize,
+    // NOTE: The URL of the later pages, with "{page}" replaced by the page
+    // number, starting at 2 for the page after url.
+    pub page_url: Option<String>,
+}
+
+impl Default for DatabaseConfig {
+    fn default() -> Self {
+        Self {
+            path: PathBuf::from("./data.db"),
+        }
+    }
+}
+
+impl Default for DeliveryConfig {
+    fn default() -> Self {
+        Self {
+            messages_interval_ms: 3000,
+            global_messages_per_second: 30,
+            max_retries: 5,
+            max_attempts: 10,
+            on_update: UpdateMode::Both,
+            seed_on_first_run: false,
+            max_age_days: None,
+        }
+    }
+}
+
+impl Default for DaemonConfig {
+    fn default() -> Self {
+        Self {
+            default_interval_secs: 600,
+            jitter_secs: 30,
+        }
+    }
+}
+
+impl Default for HttpConfig {
+    fn default() -> Self {
+        let http_client_config = HttpClientConfig::default();
+
+        Self {
+            user_agent: http_client_config.user_agent,
+            proxy: http_client_config.proxy,
+
+            connect_timeout_secs: http_client_config.connect_timeout.as_secs(),
+            read_timeout_secs: http_client_config.read_timeout.as_secs(),
+
+            max_retries: http_client_config.max_retries,
+            max_requests_per_host: http_client_config.max_requests_per_host,
+        }
+    }
+}
+
+impl Default for ScraperConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            url: None,
+            interval_secs: None,
+            pages: 1,
+        }
+    }
+}
+
+impl Config {
+    // NOTE: The config file is read from CONFIG_PATH, or ./config.toml when
+    // that variable is not set. A missing default file is not an error, so
+    // the bot can still be configured through environment variables only.
+    pub fn load() -> Result<Self, Error> {
+        let mut config = match env::var("CONFIG_PATH") {
+            Ok(path) => Self::from_file(Path::new(&path))?,
+            Err(_) if Path::new(DEFAULT_CONFIG_PATH).exists() => Self::from_file(Path::new(DEFAULT_CONFIG_PATH))?,
+            Err(_) => Self::default(),
+        };
+
+        config.apply_env_overrides()?;
+        config.validate()?;
+
+        Ok(config)
+    }
+
+    pub fn from_file(path: &Path) -> Result<Self, Error> {
+        let data = fs::read_to_string(path)
+            .map_err(|e| Error::ConfigError(format!("Could not read config file {}: {}", path.display(), e)))?;
+
+        Self::from_toml(&data)
+            .map_err(|e| Error::ConfigError(format!("Could not parse config file {}: {}", path.display(), e)))
+    }
+
+    pub fn from_toml(data: &str) -> Result<Self, toml::de::Error> {
+        toml::from_str(data)
+    }
+
+    // NOTE: Returns (provider, chat id) pairs. Without destinations nor a
+    // default chat, nothing is routed.
+    pub fn routes(&self) -> Vec<(String, String)> {
+        if self.destinations.is_empty() && self.telegram.chat_id.trim().is_empty() {
+            return vec![];
+        }
+
+        if self.destinations.is_empty() {
+            return self.scrapers
+                .providers()
+                .into_iter()
+                .filter(|(_, enabled)| *enabled)
+                .map(|(provider, _)| (provider.to_string(), self.telegram.chat_id.clone()))
+                .collect();
+        }
+
+        self.destinations
+            .iter()
+            .flat_map(|destination| {
+                destination.providers.iter().map(|provider| (provider.clone(), destination.chat_id.clone()))
+            })
+            .collect()
+    }
+
+    // NOTE: Returns (chat id, neighbourhood) pairs.
+    pub fn neighbourhood_filters(&self) -> Vec<(String, String)> {
+        self.destinations
+            .iter()
+            .flat_map(|destination| {
+                destination.neighbourhoods.iter().map(|neighbourhood| (destination.chat_id.clone(), neighbourhood.clone()))
+            })
+            .collect()
+    }
+
+    pub fn telegram_bot_config(&self) -> TelegramBotConfig {
+        TelegramBotConfig {
+            api_key: self.telegram.api_key.clone(),
+
+            per_chat_interval: Duration::from_millis(self.delivery.messages_interval_ms),
+            global_interval: Duration::from_secs(1) / self.delivery.global_messages_per_second.max(1),
+
+            max_retries: self.delivery.max_retries,
+
+            ..TelegramBotConfig::default()
+        }
+    }
+
+    pub fn http_client_config(&self) -> HttpClientConfig {
+        HttpClientConfig {
+            user_agent: self.http.user_agent.clone(),
+            proxy: self.http.proxy.clone(),
+
+            connect_timeout: Duration::from_secs(self.http.connect_timeout_secs),
+            read_timeout: Duration::from_secs(self.http.read_timeout_secs),
+
+            max_retries: self.http.max_retries,
+            max_requests_per_host: self.http.max_requests_per_host,
+
+            ..HttpClientConfig::default()
+        }
+    }
+
+    fn apply_env_overrides(&mut self) -> Result<(), Error> {
+        override_from_env("BOT_API_KEY", &mut self.telegram.api_key)?;
+        override_from_env("CHAT_ID", &mut self.telegram.chat_id)?;
+        override_from_env("BOT_OWNER_CHAT_ID", &mut self.telegram.owner_chat_id)?;
+
+        override_from_env("DATABASE_PATH", &mut self.database.path)?;
+        override_from_env("MESSAGES_INTERVAL_MS", &mut self.delivery.messages_interval_ms)?;
+        override_from_env("UPDATE_MODE", &mut self.delivery.on_update)?;
+        override_from_env("SEED_ON_FIRST_RUN", &mut self.delivery.seed_on_first_run)?;
+        if let Ok(max_age_days) = env::var("MAX_AGE_DAYS") {
+            self.delivery.max_age_days = Some(max_age_days.parse().map_err(|_| Error::ConfigError(format!("Could not parse environment variable MAX_AGE_DAYS=\"{}\"", max_age_days)))?);
+        }
+
+        override_from_env("HTTP_USER_AGENT", &mut self.http.user_agent)?;
+        if let Ok(proxy) = env::var("HTTP_PROXY_URL") {
+            self.http.proxy = Some(proxy);
+        }
+        override_from_env("HTTP_CONNECT_TIMEOUT_SECS", &mut self.http.connect_timeout_secs)?;
+        override_from_env("HTTP_READ_TIMEOUT_SECS", &mut self.http.read_timeout_secs)?;
+        override_from_env("HTTP_MAX_RETRIES", &mut self.http.max_retries)?;
+        override_from_env("MAX_REQUESTS_PER_HOST", &mut self.http.max_requests_per_host)?;
+
+        Ok(())
+    }
+
+    fn validate(&self) -> Result<(), Error> {
+        let mut problems = vec![];
+
+        if self.daemon.default_interval_secs == 0 {
+            problems.push("daemon.default_interval_secs must be greater than zero".to_string());
+        }
+
+        for (name, _) in self.scrapers.providers() {
+            if self.scrapers.interval_secs_for(name) == Some(0) {
+                problems.push(format!("interval_secs of scraper \"{}\" must be greater than zero", name));
+            }
+
+            if self.scrapers.pages_for(name) == Some(0) {
+                problems.push(format!("pages of scraper \"{}\" must be greater than zero", name));
+            }
+        }
+
+        if self.scrapers.cedae.pages > 1 {
+            problems.push("scrapers.cedae.pages must be 1, since CEDAE's news list can only be read from its first page".to_string());
+        }
+
+        if self.delivery.max_attempts == 0 {
+            problems.push("delivery.max_attempts must be greater than zero".to_string());
+        }
+
+        if self.delivery.max_age_days == Some(0) {
+            problems.push("delivery.max_age_days must be greater than zero".to_string());
+        }
+
+        if self.delivery.global_messages_per_second == 0 {
+            problems.push("delivery.global_messages_per_second must be greater than zero".to_string());
+        }
+
+        if self.http.max_requests_per_host == 0 {
+            problems.push("http.max_requests_per_host must be greater than zero".to_string());
+        }
+
+        if let Some(proxy) = &self.http.proxy {
+            if Url::parse(proxy).is_err() {
+                problems.push(format!("http.proxy \"{}\" is not a valid URL", proxy));
+            }
+        }
+
+        let scrapers = [
+            ("cedae", &self.scrapers.cedae),
+            ("rio_saneamento", &self.scrapers.rio_saneamento),
+            ("igua", &self.scrapers.igua),
+            ("aguas_do_rio", &self.scrapers.aguas_do_rio),
+        ];
+
+        for (name, scraper) in scrapers {
+            if let Some(url) = &scraper.url {
+                if Url::parse(url).is_err() {
+                    problems.push(format!("scrapers.{}.url \"{}\" is not a valid URL", name, url));
+                }
+            }
+        }
+
+        let mut generic_names = vec![];
+
+        for scraper in &self.scrapers.generic {
+            problems.extend(scraper.problems());
+
+            if generic_names.contains(&&scraper.name) {
+                problems.push(format!("scrapers.generic name \"{}\" is used more than once", scraper.name));
+            }
+
+            generic_names.push(&scraper.name);
+        }
+
+        let provider_names = self.scrapers.providers().into_iter().map(|(name, _)| name).collect::<Vec<_>>();
+
+        for destination in &self.destinations {
+            if destination.chat_id.trim().is_empty() {
+                problems.push("destinations chat_id must not be empty".to_string());
+            }
+
+            for provider in &destination.providers {
+                if !provider_names.contains(&provider.as_str()) {
+                    problems.push(format!("destinations \"{}\" provider \"{}\" is unknown (expected one of: {})", destination.chat_id, provider, provider_names.join(", ")));
+                }
+            }
+
+            for neighbourhood in &destination.neighbourhoods {
+                if !gazetteer::is_known_place(neighbourhood) {
+                    problems.push(format!("destinations \"{}\" neighbourhood \"{}\" is not in the gazetteer", destination.chat_id, neighbourhood));
+                }
+            }
+        }
+
+        into_config_error(problems)
+    }
+
+    // NOTE: Only the modes that talk to Telegram need credentials, so they
+    // are checked separately from the rest of the config.
+    pub fn validate_telegram(&self) -> Result<(), Error> {
+        let mut fields = vec![
+            ("telegram.api_key", "BOT_API_KEY", &self.telegram.api_key),
+            ("telegram.owner_chat_id", "BOT_OWNER_CHAT_ID", &self.telegram.owner_chat_id),
+        ];
+
+        if self.destinations.is_empty() {
+            fields.push(("telegram.chat_id", "CHAT_ID", &self.telegram.chat_id));
+        }
+
+        let problems = fields
+            .into_iter()
+            .filter(|(_, _, value)| value.trim().is_empty())
+            .map(|(field, env_var, _)| format!("{} is missing (set it in the config file or through {})", field, env_var))
+            .collect();
+
+        into_config_error(problems)
+    }
+}
+
+impl ScrapersConfig {
+    // NOTE: Returns every known provider name and whether it is enabled.
+    pub fn providers(&self) -> Vec<(&str, bool)> {
+        let mut providers = vec![
+            (cedae_scraper::NAME, self.cedae.enabled),
+            (rio_saneamento_scraper::NAME, self.rio_saneamento.enabled),
+            (igua_scraper::NAME, self.igua.enabled),
+            (aguas_do_rio_scraper::NAME, self.aguas_do_rio.enabled),
+        ];
+
+        providers.extend(self.generic.iter().map(|generic| (generic.name.as_str(), generic.enabled)));
+
+        providers
+    }
+
+    pub fn interval_secs_for(&self, provider: &str) -> Option<u64> {
+        match self.builtin().iter().find(|(name, _)| *name == provider) {
+            Some((_, scraper)) => scraper.interval_secs,
+            None => self.generic.iter().find(|generic| generic.name == provider).and_then(|generic| generic.interval_secs),
+        }
+    }
+
+    pub fn pages_for(&self, provider: &str) -> Option<usize> {
+        match self.builtin().iter().find(|(name, _)| *name == provider) {
+            Some((_, scraper)) => Some(scraper.pages),
+            None => self.generic.iter().find(|generic| generic.name == provider).map(|generic| generic.pages),
+        }
+    }
+
+    fn builtin(&self) -> [(&str, &ScraperConfig); 4] {
+        [
+            (cedae_scraper::NAME, &self.cedae),
+            (rio_saneamento_scraper::NAME, &self.rio_saneamento),
+            (igua_scraper::NAME, &self.igua),
+            (aguas_do_rio_scraper::NAME, &self.aguas_do_rio),
+        ]
+    }
+}
+
+impl GenericScraperConfig {
+    fn problems(&self) -> Vec<String> {
+        let mut problems = vec![];
+
+        if self.name.trim().is_empty() {
+            problems.push("scrapers.generic name must not be empty".to_string());
+        }
+
+        if Url::parse(&self.url).is_err() {
+            problems.push(format!("scrapers.generic \"{}\" url \"{}\" is not a valid URL", self.name, self.url));
+        }
+
+        let selectors = [
+            ("posts_selector", Some(&self.posts_selector)),
+            ("title_selector", Some(&self.title_selector)),
+            ("link_selector", self.link_selector.as_ref()),
+            ("date_selector", self.date_selector.as_ref()),
+            ("content_selector", self.content_selector.as_ref()),
+            ("detail_content_selector", self.detail_content_selector.as_ref()),
+        ];
+
+        for (field, selector) in selectors {
+            if let Some(selector) = selector {
+                if Selector::parse(selector).is_err() {
+                    problems.push(format!("scrapers.generic \"{}\" {} \"{}\" is not a valid CSS selector", self.name, field, selector));
+                }
+            }
+        }
+
+        if self.follow_links && self.detail_content_selector.is_none() {
+            problems.push(format!("scrapers.generic \"{}\" follows links but has no detail_content_selector", self.name));
+        }
+
+        if !self.follow_links && self.content_selector.is_none() {
+            problems.push(format!("scrapers.generic \"{}\" has no content_selector and does not follow links", self.name));
+        }
+
+        if !parses_dates(&self.date_format) {
+            problems.push(format!("scrapers.generic \"{}\" date_format \"{}\" cannot parse a date", self.name, self.date_format));
+        }
+
+        match &self.page_url {
+            Some(page_url) if !page_url.contains("{page}") || Url::parse(&page_url.replace("{page}", "2")).is_err() => {
+                problems.push(format!("scrapers.generic \"{}\" page_url \"{}\" must be a valid URL containing {{page}}", self.name, page_url));
+            },
+            None if self.pages > 1 => {
+                problems.push(format!("scrapers.generic \"{}\" reads {} pages but has no page_url", self.name, self.pages));
+            },
+            _ => {},
+        }
+
+        problems
+    }
+}
+
+impl ScraperConfig {
+    pub fn url_or(&self, default_url: &str) -> Url {
+        // NOTE: URLs were already checked by Config::validate.
+        Url::parse(self.url.as_deref().unwrap_or(default_url)).expect("Invalid scraper URL")
+    }
+}
+
+fn default_enabled() -> bool {
+    true
+}
+
+fn default_pages() -> usize {
+    1
+}
+
+fn default_date_format() -> String {
+    "%d/%m/%Y".to_string()
+}
+
+// NOTE: Formats a sample date and parses it back the way the generic scraper
+// does, so formats missing the day, month or year are caught.
+fn parses_dates(date_format: &str) -> bool {
+    if StrftimeItems::new(date_format).any(|item| item == Item::Error) {
+        return false;
+    }
+
+    let sample = NaiveDate::from_ymd_opt(2024, 10, 14).and_then(|date| date.and_hms_opt(18, 30, 0)).unwrap();
+    let text = sample.format(date_format).to_string();
+
+    NaiveDateTime::parse_from_str(&text, date_format).map(|date_time| date_time.date())
+        .or_else(|_| NaiveDate::parse_from_str(&text, date_format))
+        .is_ok_and(|date| date == sample.date())
+}
+
+impl FromStr for UpdateMode {
+    type Err = ();
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "edit" => Ok(UpdateMode::Edit),
+            "new" => Ok(UpdateMode::New),
+            "both" => Ok(UpdateMode::Both),
+            _ => Err(()),
+        }
+    }
+}
+
+fn override_from_env<T: FromStr>(name: &str, field: &mut T) -> Result<(), Error> {
+    if let Ok(value) = env::var(name) {
+        *field = value.parse().map_err(|_| Error::ConfigError(format!("Could not parse environment variable {}=\"{}\"", name, value)))?;
+    }
+
+    Ok(())
+}
+
+fn into_config_error(problems: Vec<String>) -> Result<(), Error> {
+    if problems.is_empty() {
+        return Ok(());
+    }
+
+    Err(Error::ConfigError(problems.join("\n")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Config, UpdateMode};
+
+    #[test]
+    fn parses_example_config() {
+        let config = Config::from_toml(include_str!("../config.example.toml")).unwrap();
+
+        assert!(config.validate().is_ok());
+        assert!(config.validate_telegram().is_err());
+        assert!(config.scrapers.cedae.enabled);
+        assert_eq!(config.delivery.messages_interval_ms, 3000);
+        assert_eq!(config.delivery.on_update, UpdateMode::Both);
+        assert!(!config.delivery.seed_on_first_run);
+        assert_eq!(config.delivery.max_age_days, Some(30));
+    }
+
+    #[test]
+    fn reports_every_invalid_field() {
+        let config = Config::from_toml(r#"
+            [http]
+            max_requests_per_host = 0
+
+            [scrapers.igua]
+            url = "not a url"
+        "#).unwrap();
+
+        let error = config.validate().unwrap_err().to_string();
+
+        assert!(error.contains("http.max_requests_per_host"));
+        assert!(error.contains("scrapers.igua.url"));
+    }
+
+    #[test]
+    fn validates_generic_scrapers() {
+        let config = Config::from_toml(r#"
+            [[scrapers.generic]]
+            name = "Prolagos"
+            url = "https://prolagos.com.br/noticias/"
+            posts_selector = "article"
+            title_selector = "h2 >"
+            follow_links = true
+
+            [[scrapers.generic]]
+            name = "Águas de Niterói"
+            url = "https://aguasdeniteroi.com.br/noticias/"
+            posts_selector = "article"
+            title_selector = "h2"
+            date_format = "%d/%m"
+
+            [[scrapers.generic]]
+            name = "CAB"
+            url = "https://cab.com.br/noticias/"
+            posts_selector = "article"
+            title_selector = "h2"
+            content_selector = "p"
+            date_format = "%d/%m/%Y %Q"
+        "#).unwrap();
+
+        let error = config.validate().unwrap_err().to_string();
+
+        assert!(error.contains("title_selector"));
+        assert!(error.contains("detail_content_selector"));
+        assert!(error.contains("\"Águas de Niterói\" has no content_selector"));
+        assert!(error.contains("date_format \"%d/%m\""));
+        assert!(error.contains("date_format \"%d/%m/%Y %Q\""));
+        assert!(!error.contains("\"CAB\" has no content_selector"));
+    }
+
+    #[test]
+    fn routes_every_enabled_provider_to_the_default_chat() {
+        let config = Config::from_toml(r#"
+            [telegram]
+            chat_id = "-100"
+
+            [scrapers.igua]
+            enabled = false
+        "#).unwrap();
+
+        assert_eq!(config.routes(), vec![
+            ("CEDAE".to_string(), "-100".to_string()),
+            ("Rio+Saneamento".to_string(), "-100".to_string()),
+            ("Águas do Rio".to_string(), "-100".to_string()),
+        ]);
+        assert!(Config::from_toml("[telegram]\nchat_id = \" \"").unwrap().routes().is_empty());
+        assert!(Config::default().routes().is_empty());
+    }
+
+    #[test]
+    fn routes_providers_to_destinations() {
+        let config = Config::from_toml(r#"
+            [[destinations]]
+            chat_id = "-1"
+            providers = ["CEDAE", "Águas do Rio"]
+
+            [[destinations]]
+            chat_id = "-2"
+            providers = ["Iguá", "Prolagos"]
+        "#).unwrap();
+
+        assert!(config.validate().unwrap_err().to_string().contains("\"Prolagos\" is unknown"));
+        assert_eq!(config.routes(), vec![
+            ("CEDAE".to_string(), "-1".to_string()),
+            ("Águas do Rio".to_string(), "-1".to_string()),
+            ("Iguá".to_string(), "-2".to_string()),
+            ("Prolagos".to_string(), "-2".to_string()),
+        ]);
+    }
+
+    #[test]
+    fn validates_destination_neighbourhoods() {
+        let config = Config::from_toml(r#"
+            [[destinations]]
+            chat_id = "-1"
+            providers = ["CEDAE"]
+            neighbourhoods = ["campo grande", "Niterói", "Atlântida"]
+        "#).unwrap();
+
+        let error = config.validate().unwrap_err().to_string();
+
+        assert!(error.contains("\"Atlântida\" is not in the gazetteer"));
+        assert!(!error.contains("campo grande"));
+        assert_eq!(config.neighbourhood_filters().len(), 3);
+    }
+
+    #[test]
+    fn reads_scraper_intervals() {
+        let config = Config::from_toml(r#"
+            [scrapers.cedae]
+            interval_secs = 3600
+
+            [[scrapers.generic]]
+            name = "Prolagos"
+            url = "https://prolagos.com.br/noticias/"
+            posts_selector = "article"
+            title_selector = "h2"
+            interval_secs = 0
+        "#).unwrap();
+
+        assert_eq!(config.scrapers.interval_secs_for("CEDAE"), Some(3600));
+        assert_eq!(config.scrapers.interval_secs_for("Iguá"), None);
+        assert!(config.validate().unwrap_err().to_string().contains("\"Prolagos\" must be greater than zero"));
+    }
+
+    #[test]
+    fn reads_scraper_pages() {
+        let config = Config::from_toml(r#"
+            [scrapers.cedae]
+            pages = 2
+
+            [scrapers.igua]
+            pages = 3
+
+            [[scrapers.generic]]
+            name = "Prolagos"
+            url = "https://prolagos.com.br/noticias/"
+            posts_selector = "article"
+            title_selector = "h2"
+            pages = 2
+        "#).unwrap();
+
+        assert_eq!(config.scrapers.pages_for("Iguá"), Some(3));
+        assert_eq!(config.scrapers.pages_for("CEDAE"), Some(2));
+        assert_eq!(config.scrapers.pages_for("Prolagos"), Some(2));
+        assert_eq!(config.scrapers.pages_for("Rio+Saneamento"), Some(1));
+
+        let error = config.validate().unwrap_err().to_string();
+        assert!(error.contains("\"Prolagos\" reads 2 pages but has no page_url"));
+        assert!(error.contains("scrapers.cedae.pages must be 1"));
+    }
+
+    #[test]
+    fn rejects_unknown_fields() {
+        assert!(Config::from_toml("[scrapers.prolagos]\nenabled = true").is_err());
+    }
+}
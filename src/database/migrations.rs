@@ -0,0 +1,429 @@
+use chrono::{DateTime, NaiveDate};
+use rusqlite::Connection;
+
+use crate::{error::Error, news_post::{local_date, TIMEZONE}};
+
+type Migration = fn(&Connection) -> Result<(), Error>;
+
+// NOTE: Every migration runs once, in order, inside its own transaction, and
+// PRAGMA user_version holds how many were applied. Released migrations must
+// never be edited; schema changes go in a new one at the end.
+//
+// Databases created before migrations existed are at version 0 but may
+// already have some of these tables, so the early migrations must not fail
+// on them.
+const MIGRATIONS: &[Migration] = &[
+    create_posts,
+    create_routes_and_deliveries,
+    create_post_revisions,
+    create_delivered_messages,
+    create_outbox,
+    add_post_contents,
+    repair_post_dates,
+    create_posts_search,
+    add_route_creation_times,
+    key_posts_search_by_id,
+    add_post_date_times,
+    clear_unsent_delivery_times,
+];
+
+pub fn run(connection: &mut Connection) -> Result<(), Error> {
+    let version = schema_version(connection)?;
+
+    if version > MIGRATIONS.len() {
+        return Err(Error::UnsupportedDatabaseVersion(version));
+    }
+
+    for (index, migration) in MIGRATIONS.iter().enumerate().skip(version) {
+        let transaction = connection.transaction()?;
+
+        migration(&transaction)?;
+        transaction.pragma_update(None, "user_version", index + 1)?;
+
+        transaction.commit()?;
+    }
+
+    Ok(())
+}
+
+pub fn schema_version(connection: &Connection) -> Result<usize, Error> {
+    connection.query_row("PRAGMA user_version", [], |row| row.get(0)).map_err(|e| e.into())
+}
+
+fn create_posts(connection: &Connection) -> Result<(), Error> {
+    connection.execute("CREATE TABLE IF NOT EXISTS Posts (
+        id    TEXT PRIMARY KEY,
+        date  DATETIME,
+        handledAt  DATETIME
+    )", ())?;
+
+    Ok(())
+}
+
+fn create_routes_and_deliveries(connection: &Connection) -> Result<(), Error> {
+    // NOTE: Routes with fromConfig = 1 are rewritten from the config file
+    // on every run. Other routes are left untouched.
+    connection.execute("CREATE TABLE IF NOT EXISTS Routes (
+        provider    TEXT NOT NULL,
+        chatId      TEXT NOT NULL,
+        fromConfig  INTEGER NOT NULL DEFAULT 0,
+        PRIMARY KEY (provider, chatId)
+    )", ())?;
+
+    connection.execute("CREATE TABLE IF NOT EXISTS ChatNeighbourhoods (
+        chatId         TEXT NOT NULL,
+        neighbourhood  TEXT NOT NULL,
+        fromConfig     INTEGER NOT NULL DEFAULT 0,
+        PRIMARY KEY (chatId, neighbourhood)
+    )", ())?;
+
+    connection.execute("CREATE TABLE IF NOT EXISTS Deliveries (
+        postId       TEXT NOT NULL,
+        chatId       TEXT NOT NULL,
+        deliveredAt  DATETIME,
+        PRIMARY KEY (postId, chatId)
+    )", ())?;
+
+    Ok(())
+}
+
+fn create_post_revisions(connection: &Connection) -> Result<(), Error> {
+    // NOTE: Keeps the content of every revision seen, so an edited post
+    // can be compared against the one previously delivered.
+    connection.execute("CREATE TABLE IF NOT EXISTS PostRevisions (
+        postId    TEXT NOT NULL,
+        revision  TEXT NOT NULL,
+        content   TEXT NOT NULL,
+        seenAt    DATETIME,
+        PRIMARY KEY (postId, revision)
+    )", ())?;
+
+    Ok(())
+}
+
+fn create_delivered_messages(connection: &Connection) -> Result<(), Error> {
+    // NOTE: A post may be split into several messages, numbered by part.
+    connection.execute("CREATE TABLE IF NOT EXISTS DeliveredMessages (
+        postId     TEXT NOT NULL,
+        chatId     TEXT NOT NULL,
+        part       INTEGER NOT NULL,
+        messageId  INTEGER NOT NULL,
+        PRIMARY KEY (postId, chatId, part)
+    )", ())?;
+
+    Ok(())
+}
+
+fn create_outbox(connection: &Connection) -> Result<(), Error> {
+    // NOTE: Messages waiting to be delivered. Entries go from pending to
+    // sending to sent, or back to pending when sending fails, until
+    // they run out of attempts and are marked as failed. Entries left
+    // in sending by a crash are resumed on the next run.
+    connection.execute("CREATE TABLE IF NOT EXISTS Outbox (
+        id            INTEGER PRIMARY KEY AUTOINCREMENT,
+        postId        TEXT NOT NULL,
+        chatId        TEXT NOT NULL,
+        kind          TEXT NOT NULL,
+        text          TEXT NOT NULL,
+        fallbackText  TEXT,
+        state         TEXT NOT NULL DEFAULT 'pending',
+        attempts      INTEGER NOT NULL DEFAULT 0,
+        lastError     TEXT,
+        createdAt     DATETIME,
+        updatedAt     DATETIME
+    )", ())?;
+
+    Ok(())
+}
+
+fn add_post_contents(connection: &Connection) -> Result<(), Error> {
+    // NOTE: Posts saved before this migration keep these columns NULL until
+    // they are scraped again.
+    let post_columns = [
+        ("provider", "TEXT"),
+        ("title", "TEXT"),
+        ("url", "TEXT"),
+        ("content", "TEXT"),
+        ("contentHash", "TEXT"),
+        ("scrapedAt", "DATETIME"),
+    ];
+    for (column, column_type) in post_columns {
+        add_missing_column(connection, "Posts", column, column_type)?;
+    }
+
+    connection.execute("CREATE INDEX IF NOT EXISTS PostsByProviderAndDate ON Posts (provider, date)", ())?;
+
+    Ok(())
+}
+
+// NOTE: Undated posts used to be saved with the string "NULL", and dates
+// without a time or timezone.
+fn repair_post_dates(connection: &Connection) -> Result<(), Error> {
+    connection.execute("UPDATE Posts SET date = NULL WHERE date = 'NULL'", ())?;
+
+    let dates = {
+        let mut stmt = connection.prepare("SELECT DISTINCT date FROM Posts WHERE date IS NOT NULL")?;
+        let rows = stmt.query_map([], |row| row.get::<_, String>(0))?;
+
+        rows.collect::<Result<Vec<_>, _>>()?
+    };
+
+    let mut stmt = connection.prepare("UPDATE Posts SET date = ?2 WHERE date = ?1")?;
+    for date in dates {
+        if let Ok(parsed_date) = NaiveDate::parse_from_str(&date, "%Y-%m-%d") {
+            stmt.execute([&date, &local_date(parsed_date).date_time.to_rfc3339()])?;
+        }
+    }
+
+    Ok(())
+}
+
+// NOTE: A full-text index over the posts table, kept up to date by triggers.
+// Accents are ignored, so "agua" finds "água".
+fn create_posts_search(connection: &Connection) -> Result<(), Error> {
+    connection.execute_batch("
+        CREATE VIRTUAL TABLE IF NOT EXISTS PostsSearch USING fts5 (
+            title,
+            content,
+            content = 'Posts',
+            content_rowid = 'rowid',
+            tokenize = 'unicode61 remove_diacritics 2'
+        );
+
+        CREATE TRIGGER IF NOT EXISTS PostsSearchInsert AFTER INSERT ON Posts BEGIN
+            INSERT INTO PostsSearch (rowid, title, content) VALUES (new.rowid, new.title, new.content);
+        END;
+
+        CREATE TRIGGER IF NOT EXISTS PostsSearchDelete AFTER DELETE ON Posts BEGIN
+            INSERT INTO PostsSearch (PostsSearch, rowid, title, content) VALUES ('delete', old.rowid, old.title, old.content);
+        END;
+
+        CREATE TRIGGER IF NOT EXISTS PostsSearchUpdate AFTER UPDATE ON Posts BEGIN
+            INSERT INTO PostsSearch (PostsSearch, rowid, title, content) VALUES ('delete', old.rowid, old.title, old.content);
+            INSERT INTO PostsSearch (rowid, title, content) VALUES (new.rowid, new.title, new.content);
+        END;
+
+        INSERT INTO PostsSearch (PostsSearch) VALUES ('rebuild');
+    ")?;
+
+    Ok(())
+}
+
+// NOTE: Only routes added through bot commands get a creation time. Routes
+// from the config file keep it NULL.
+fn add_route_creation_times(connection: &Connection) -> Result<(), Error> {
+    add_missing_column(connection, "Routes", "createdAt", "DATETIME")
+}
+
+// NOTE: Posts has no INTEGER PRIMARY KEY, so its rowids may be renumbered
+// by VACUUM. The index keeps its own copy of the text and the post id instead
+// of pointing at those rowids.
+fn key_posts_search_by_id(connection: &Connection) -> Result<(), Error> {
+    connection.execute_batch("
+        DROP TRIGGER IF EXISTS PostsSearchInsert;
+        DROP TRIGGER IF EXISTS PostsSearchDelete;
+        DROP TRIGGER IF EXISTS PostsSearchUpdate;
+        DROP TABLE IF EXISTS PostsSearch;
+
+        CREATE VIRTUAL TABLE PostsSearch USING fts5 (
+            id UNINDEXED,
+            title,
+            content,
+            tokenize = 'unicode61 remove_diacritics 2'
+        );
+
+        CREATE TRIGGER PostsSearchInsert AFTER INSERT ON Posts BEGIN
+            INSERT INTO PostsSearch (id, title, content) VALUES (new.id, new.title, new.content);
+        END;
+
+        CREATE TRIGGER PostsSearchDelete AFTER DELETE ON Posts BEGIN
+            DELETE FROM PostsSearch WHERE id = old.id;
+        END;
+
+        CREATE TRIGGER PostsSearchUpdate AFTER UPDATE ON Posts BEGIN
+            DELETE FROM PostsSearch WHERE id = old.id;
+            INSERT INTO PostsSearch (id, title, content) VALUES (new.id, new.title, new.content);
+        END;
+
+        INSERT INTO PostsSearch (id, title, content) SELECT id, title, content FROM Posts;
+    ")?;
+
+    Ok(())
+}
+
+// NOTE: Whether a stored post had a time was never recorded, so it is
+// guessed from the dates themselves: only those placed at the start of their
+// day, as local_date does, are taken as dates without a time.
+fn add_post_date_times(connection: &Connection) -> Result<(), Error> {
+    add_missing_column(connection, "Posts", "dateHasTime", "BOOLEAN")?;
+
+    let dates = {
+        let mut stmt = connection.prepare("SELECT DISTINCT date FROM Posts WHERE date IS NOT NULL")?;
+        let rows = stmt.query_map([], |row| row.get::<_, String>(0))?;
+
+        rows.collect::<Result<Vec<_>, _>>()?
+    };
+
+    let mut stmt = connection.prepare("UPDATE Posts SET dateHasTime = ?2 WHERE date = ?1")?;
+    for date in dates {
+        if let Ok(parsed_date) = DateTime::parse_from_rfc3339(&date) {
+            let parsed_date = parsed_date.with_timezone(&TIMEZONE);
+            stmt.execute(rusqlite::params![&date, parsed_date != local_date(parsed_date.date_naive()).date_time])?;
+        }
+    }
+
+    Ok(())
+}
+
+// NOTE: Deliveries used to get their time when enqueued, so posts still
+// waiting in the outbox, or that ran out of attempts, looked delivered.
+fn clear_unsent_delivery_times(connection: &Connection) -> Result<(), Error> {
+    connection.execute("UPDATE Deliveries SET deliveredAt = NULL
+        WHERE EXISTS (SELECT 1 FROM Outbox WHERE Outbox.postId = Deliveries.postId AND Outbox.chatId = Deliveries.chatId AND kind = 'post' AND state != 'sent')
+        AND NOT EXISTS (SELECT 1 FROM Outbox WHERE Outbox.postId = Deliveries.postId AND Outbox.chatId = Deliveries.chatId AND kind = 'post' AND state = 'sent')", ())?;
+
+    Ok(())
+}
+
+fn add_missing_column(connection: &Connection, table: &str, column: &str, column_type: &str) -> Result<(), Error> {
+    let mut stmt = connection.prepare(&format!("SELECT 1 FROM pragma_table_info('{}') WHERE name = ?1", table))?;
+    if stmt.exists([column])? {
+        return Ok(());
+    }
+
+    connection.execute(&format!("ALTER TABLE {} ADD COLUMN {} {}", table, column, column_type), ())?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs, path::PathBuf};
+
+    use rusqlite::Connection;
+
+    use crate::{database::Database, error::Error, news_post::NewsPost};
+
+    use super::{schema_version, MIGRATIONS};
+
+    fn database_path(name: &str) -> PathBuf {
+        let directory = env::temp_dir().join(format!("migrations-test-{}", std::process::id()));
+        fs::create_dir_all(&directory).unwrap();
+
+        let path = directory.join(name);
+        let _ = fs::remove_file(&path);
+
+        path
+    }
+
+    #[test]
+    fn migrates_the_baseline_schema() {
+        let path = database_path("baseline.db");
+
+        let connection = Connection::open(&path).unwrap();
+        connection.execute("CREATE TABLE Posts (id TEXT PRIMARY KEY, date DATETIME, handledAt DATETIME)", ()).unwrap();
+        connection.execute("INSERT INTO Posts (id, date, handledAt) VALUES ('old', '2024-10-14', datetime('now'))", ()).unwrap();
+        connection.execute("INSERT INTO Posts (id, date, handledAt) VALUES ('undated', 'NULL', datetime('now'))", ()).unwrap();
+        connection.execute("INSERT INTO Posts (id, date, handledAt) VALUES ('daylight-saving', '2018-11-04', datetime('now'))", ()).unwrap();
+        connection.execute("INSERT INTO Posts (id, date, handledAt) VALUES ('timed', '2024-10-14T15:30:00-03:00', datetime('now'))", ()).unwrap();
+        drop(connection);
+
+        let database = Database::new(&path).unwrap();
+        database.save_post(&NewsPost::new("CEDAE", "Obra".to_string(), "https://cedae.com.br/obra".to_string(), "Previsão: 18h.".to_string(), None)).unwrap();
+
+        assert!(database.post_exists("old").unwrap());
+        assert_eq!(database.latest_posts(10).unwrap().len(), 1);
+        drop(database);
+
+        let connection = Connection::open(&path).unwrap();
+        assert_eq!(schema_version(&connection).unwrap(), MIGRATIONS.len());
+
+        let dates = connection.prepare("SELECT date FROM Posts WHERE id IN ('old', 'undated') ORDER BY id").unwrap()
+            .query_map([], |row| row.get::<_, Option<String>>(0)).unwrap()
+            .collect::<Result<Vec<_>, _>>().unwrap();
+        assert_eq!(dates, vec![Some("2024-10-14T00:00:00-03:00".to_string()), None]);
+
+        let date_times = connection.prepare("SELECT dateHasTime FROM Posts WHERE id IN ('daylight-saving', 'old', 'timed', 'undated') ORDER BY id").unwrap()
+            .query_map([], |row| row.get::<_, Option<bool>>(0)).unwrap()
+            .collect::<Result<Vec<_>, _>>().unwrap();
+        assert_eq!(date_times, vec![Some(false), Some(false), Some(true), None]);
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn only_runs_new_migrations() {
+        let path = database_path("current.db");
+
+        Database::new(&path).unwrap().save_post(&NewsPost::new("CEDAE", "Obra".to_string(), "https://cedae.com.br/obra".to_string(), "Previsão: 18h.".to_string(), None)).unwrap();
+        let database = Database::new(&path).unwrap();
+
+        assert_eq!(database.latest_posts(10).unwrap().len(), 1);
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn rebuilds_the_search_index_by_post_id() {
+        let path = database_path("search.db");
+        let post = NewsPost::new("CEDAE", "Falta d'água em Bangu".to_string(), "https://cedae.com.br/falta".to_string(), "Manutenção na rede.".to_string(), None);
+
+        // NOTE: Stops right before key_posts_search_by_id, when the index still pointed at the rowids of Posts.
+        let connection = Connection::open(&path).unwrap();
+        for migration in &MIGRATIONS[..9] {
+            migration(&connection).unwrap();
+        }
+        connection.pragma_update(None, "user_version", 9).unwrap();
+        connection.execute("INSERT INTO Posts (id, provider, title, url, content, scrapedAt) VALUES (?1, ?2, ?3, ?4, ?5, datetime('now'))",
+            rusqlite::params![post.id(), post.provider(), post.title(), post.url(), post.content()]).unwrap();
+        drop(connection);
+
+        let database = Database::new(&path).unwrap();
+        assert_eq!(database.search_posts("bangu", 10).unwrap(), vec![post.clone()]);
+        drop(database);
+
+        let connection = Connection::open(&path).unwrap();
+        let ids = connection.prepare("SELECT id FROM PostsSearch").unwrap()
+            .query_map([], |row| row.get::<_, String>(0)).unwrap()
+            .collect::<Result<Vec<_>, _>>().unwrap();
+        assert_eq!(ids, vec![post.id().to_string()]);
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn clears_the_times_of_unsent_deliveries() {
+        let path = database_path("deliveries.db");
+
+        // NOTE: Stops right before clear_unsent_delivery_times.
+        let connection = Connection::open(&path).unwrap();
+        for migration in &MIGRATIONS[..11] {
+            migration(&connection).unwrap();
+        }
+        connection.pragma_update(None, "user_version", 11).unwrap();
+        for (chat_id, state) in [("-1", "sent"), ("-2", "failed"), ("-3", "pending")] {
+            connection.execute("INSERT INTO Outbox (postId, chatId, kind, text, state) VALUES ('post', ?1, 'post', '<b>Obra</b>', ?2)", [chat_id, state]).unwrap();
+            connection.execute("INSERT INTO Deliveries (postId, chatId, deliveredAt) VALUES ('post', ?1, datetime('now'))", [chat_id]).unwrap();
+        }
+        drop(connection);
+
+        let database = Database::new(&path).unwrap();
+        let chat_ids = database.deliveries_for_post("post").unwrap().into_iter().map(|(chat_id, _)| chat_id).collect::<Vec<_>>();
+
+        assert_eq!(chat_ids, vec!["-1"]);
+        assert!(database.delivery_exists("post", "-3").unwrap());
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn refuses_databases_from_newer_versions() {
+        let path = database_path("newer.db");
+
+        Connection::open(&path).unwrap().pragma_update(None, "user_version", MIGRATIONS.len() + 1).unwrap();
+
+        assert!(matches!(Database::new(&path), Err(Error::UnsupportedDatabaseVersion(_))));
+
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -1,27 +1,86 @@
-use chrono::NaiveDate;
-use rusqlite::Connection;
+use std::{path::Path, time::Duration};
 
-use crate::error::Error;
+use chrono::DateTime;
+use rusqlite::{backup::Backup, Connection, OpenFlags, Row, Transaction};
+
+use crate::{error::Error, news_post::{NewsPost, PostDate, TIMEZONE}};
+
+mod migrations;
 
 pub struct Database {
     connection: Connection,
 }
 
-impl<'a> Database {
-    pub fn new() -> Result<Self, Error> {
-        let connection = Connection::open("./data.db")?;
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutboxKind {
+    // NOTE: The post itself, sent to a chat for the first time.
+    Post,
+    // NOTE: A new message describing what changed in an edited post.
+    Update,
+    // NOTE: The edited post, replacing the messages previously sent. The
+    // fallback text is sent instead when those messages are not known.
+    Edit,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct OutboxEntry {
+    pub id: i64,
+    pub post_id: String,
+    pub chat_id: String,
+    pub kind: OutboxKind,
+    pub text: String,
+    pub fallback_text: Option<String>,
+    pub attempts: u32,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct UnsentOutboxEntry {
+    pub chat_id: String,
+    pub kind: OutboxKind,
+    pub state: String,
+    pub last_error: Option<String>,
+}
+
+impl Database {
+    pub fn new(path: &Path) -> Result<Self, Error> {
+        let mut connection = Connection::open(path)?;
+
+        migrations::run(&mut connection)?;
+
+        Ok(Self {
+            connection
+        })
+    }
+
+    // NOTE: An in-memory copy of the database at the path, or an empty one
+    // when there is no file yet. Nothing done to it is written back, so it
+    // can be used to preview a run.
+    pub fn snapshot(path: &Path) -> Result<Self, Error> {
+        let mut connection = Connection::open_in_memory()?;
+
+        if path.exists() {
+            let source = Connection::open_with_flags(path, OpenFlags::SQLITE_OPEN_READ_ONLY)?;
+            Backup::new(&source, &mut connection)?.run_to_completion(100, Duration::ZERO, None)?;
+        }
 
-        connection.execute("CREATE TABLE IF NOT EXISTS Posts (
-            id    TEXT PRIMARY KEY,
-            date  DATETIME,
-            handledAt  DATETIME
-        )", ())?;
+        migrations::run(&mut connection)?;
 
         Ok(Self {
             connection
         })
     }
 
+    // NOTE: Other methods run inside the transaction until it is committed.
+    // Methods that start their own transaction must not be called meanwhile.
+    pub fn transaction(&self) -> Result<Transaction<'_>, Error> {
+        self.connection.unchecked_transaction().map_err(|e| e.into())
+    }
+
+    // NOTE: Posts stored before providers were recorded count for every provider.
+    pub fn provider_has_posts(&self, provider: &str) -> Result<bool, Error> {
+        self.connection.query_row("SELECT EXISTS (SELECT 1 FROM Posts WHERE provider = ?1 OR provider IS NULL)", [provider], |row| row.get(0)).map_err(|e| e.into())
+    }
+
     pub fn post_exists(&self, id: &str) -> Result<bool, Error> {
         let mut stmt = self.connection.prepare("SELECT id FROM Posts WHERE id = ?1")?;
         let mut rows = stmt.query([id])?;
@@ -29,12 +88,600 @@ impl<'a> Database {
         rows.next().map(|r| r.is_some()).map_err(|e| e.into())
     }
 
-    pub fn save_post(&self, id: &str, date: &Option<NaiveDate>) -> Result<(), Error>{
-        let date_str = date.map(|d| d.format("%Y-%m-%d").to_string()).unwrap_or("NULL".to_string());
+    // NOTE: Inserts the post or, when its content changed, replaces what was
+    // stored. scrapedAt is when the stored content was scraped.
+    pub fn save_post(&self, post: &NewsPost) -> Result<(), Error>{
+        let date_str = post.date().map(|d| d.to_rfc3339());
 
-        let mut stmt = self.connection.prepare("INSERT INTO Posts (id, date, handledAt) VALUES (?1, ?2, datetime('now'))")?;
-        stmt.execute(rusqlite::params![id, &date_str])?;
+        let mut stmt = self.connection.prepare("INSERT INTO Posts (id, date, dateHasTime, handledAt, provider, title, url, content, contentHash, scrapedAt)
+            VALUES (?1, ?2, ?3, datetime('now'), ?4, ?5, ?6, ?7, ?8, datetime('now'))
+            ON CONFLICT (id) DO UPDATE SET
+                date = excluded.date,
+                dateHasTime = excluded.dateHasTime,
+                provider = excluded.provider,
+                title = excluded.title,
+                url = excluded.url,
+                content = excluded.content,
+                contentHash = excluded.contentHash,
+                scrapedAt = excluded.scrapedAt
+            WHERE contentHash IS NOT excluded.contentHash")?;
+        stmt.execute(rusqlite::params![post.id(), &date_str, post.date_has_time(), post.provider(), post.title(), post.url(), post.content(), post.revision()])?;
 
         Ok(())
     }
-}
\ No newline at end of file
+
+    // NOTE: Newest first, with undated posts after the dated ones. Dates are
+    // compared in UTC, since their offsets may differ. Posts saved by older
+    // versions have no content and are skipped.
+    pub fn latest_posts(&self, limit: usize) -> Result<Vec<NewsPost>, Error> {
+        let mut stmt = self.connection.prepare("SELECT provider, title, url, content, date, dateHasTime FROM Posts
+            WHERE provider IS NOT NULL
+            ORDER BY date IS NULL, datetime(date) DESC, handledAt DESC
+            LIMIT ?1")?;
+        let rows = stmt.query_map([limit], post_from_row)?;
+
+        rows.collect::<Result<_, _>>().map_err(|e| e.into())
+    }
+
+    pub fn post(&self, id: &str) -> Result<Option<NewsPost>, Error> {
+        let mut stmt = self.connection.prepare("SELECT provider, title, url, content, date, dateHasTime FROM Posts WHERE id = ?1 AND provider IS NOT NULL")?;
+        let mut rows = stmt.query_map([id], post_from_row)?;
+
+        rows.next().transpose().map_err(|e| e.into())
+    }
+
+    // NOTE: Removes the post and everything recorded about its deliveries,
+    // so the next run handles it as new. Returns whether it existed.
+    pub fn forget_post(&self, id: &str) -> Result<bool, Error> {
+        let transaction = self.connection.unchecked_transaction()?;
+
+        let removed = transaction.execute("DELETE FROM Posts WHERE id = ?1", [id])? > 0;
+        for table in ["PostRevisions", "Deliveries", "DeliveredMessages", "Outbox"] {
+            transaction.execute(&format!("DELETE FROM {} WHERE postId = ?1", table), [id])?;
+        }
+
+        transaction.commit()?;
+
+        Ok(removed)
+    }
+
+    // NOTE: Returns (revision, content).
+    pub fn latest_revision(&self, post_id: &str) -> Result<Option<(String, String)>, Error> {
+        let mut stmt = self.connection.prepare("SELECT revision, content FROM PostRevisions WHERE postId = ?1 ORDER BY seenAt DESC, rowid DESC LIMIT 1")?;
+        let mut rows = stmt.query([post_id])?;
+
+        match rows.next()? {
+            Some(row) => Ok(Some((row.get(0)?, row.get(1)?))),
+            None => Ok(None),
+        }
+    }
+
+    pub fn save_revision(&self, post_id: &str, revision: &str, content: &str) -> Result<(), Error> {
+        let mut stmt = self.connection.prepare("INSERT OR REPLACE INTO PostRevisions (postId, revision, content, seenAt) VALUES (?1, ?2, ?3, datetime('now'))")?;
+        stmt.execute([post_id, revision, content])?;
+
+        Ok(())
+    }
+
+    // NOTE: Posts used to be identified by the hash of their content, which
+    // is now their revision. A post still stored under that hash is moved to
+    // its stable id, so it is not delivered again.
+    pub fn adopt_legacy_post(&self, post_id: &str, revision: &str) -> Result<(), Error> {
+        if post_id == revision || self.post_exists(post_id)? || !self.post_exists(revision)? {
+            return Ok(());
+        }
+
+        let transaction = self.connection.unchecked_transaction()?;
+
+        transaction.execute("UPDATE Posts SET id = ?1 WHERE id = ?2", [post_id, revision])?;
+        transaction.execute("UPDATE OR IGNORE Deliveries SET postId = ?1 WHERE postId = ?2", [post_id, revision])?;
+
+        transaction.commit().map_err(|e| e.into())
+    }
+
+    // NOTE: Every word must match, either whole or as the start of a longer
+    // word. Title matches weigh more than content matches, and relevance is
+    // divided by 1 + age / 30 days, so recent posts come first among similar
+    // ones.
+    pub fn search_posts(&self, query: &str, limit: usize) -> Result<Vec<NewsPost>, Error> {
+        let terms = query
+            .split(|c: char| !c.is_alphanumeric())
+            .filter(|term| !term.is_empty())
+            .map(|term| format!("\"{}\"*", term))
+            .collect::<Vec<_>>();
+
+        if terms.is_empty() {
+            return Ok(vec![]);
+        }
+
+        let mut stmt = self.connection.prepare("SELECT Posts.provider, Posts.title, Posts.url, Posts.content, Posts.date, Posts.dateHasTime FROM PostsSearch
+            JOIN Posts ON Posts.id = PostsSearch.id
+            WHERE PostsSearch MATCH ?1 AND Posts.provider IS NOT NULL
+            ORDER BY bm25(PostsSearch, 0.0, 5.0, 1.0) / (1.0 + (julianday('now') - julianday(COALESCE(Posts.date, Posts.scrapedAt))) / 30.0)
+            LIMIT ?2")?;
+        let rows = stmt.query_map(rusqlite::params![terms.join(" "), limit], post_from_row)?;
+
+        rows.collect::<Result<_, _>>().map_err(|e| e.into())
+    }
+
+    pub fn sync_config_routes(&self, routes: &[(String, String)]) -> Result<(), Error> {
+        self.sync_config_rows("Routes", "provider, chatId", routes)
+    }
+
+    pub fn sync_config_neighbourhoods(&self, neighbourhoods: &[(String, String)]) -> Result<(), Error> {
+        self.sync_config_rows("ChatNeighbourhoods", "chatId, neighbourhood", neighbourhoods)
+    }
+
+    pub fn neighbourhoods_for_chat(&self, chat_id: &str) -> Result<Vec<String>, Error> {
+        let mut stmt = self.connection.prepare("SELECT neighbourhood FROM ChatNeighbourhoods WHERE chatId = ?1 ORDER BY neighbourhood")?;
+        let rows = stmt.query_map([chat_id], |row| row.get(0))?;
+
+        rows.collect::<Result<_, _>>().map_err(|e| e.into())
+    }
+
+    pub fn chats_for_provider(&self, provider: &str) -> Result<Vec<String>, Error> {
+        let mut stmt = self.connection.prepare("SELECT chatId FROM Routes WHERE provider = ?1 ORDER BY chatId")?;
+        let rows = stmt.query_map([provider], |row| row.get(0))?;
+
+        rows.collect::<Result<_, _>>().map_err(|e| e.into())
+    }
+
+    pub fn providers_for_chat(&self, chat_id: &str) -> Result<Vec<String>, Error> {
+        let mut stmt = self.connection.prepare("SELECT provider FROM Routes WHERE chatId = ?1 ORDER BY provider")?;
+        let rows = stmt.query_map([chat_id], |row| row.get(0))?;
+
+        rows.collect::<Result<_, _>>().map_err(|e| e.into())
+    }
+
+    pub fn add_route(&self, provider: &str, chat_id: &str) -> Result<(), Error> {
+        self.connection.execute("INSERT OR IGNORE INTO Routes (provider, chatId, fromConfig, createdAt) VALUES (?1, ?2, 0, strftime('%Y-%m-%d %H:%M:%f', 'now'))", [provider, chat_id])?;
+
+        Ok(())
+    }
+
+    // NOTE: Whether the post was already stored when the chat subscribed to
+    // its provider. Such posts are not sent, so a new subscriber does not get
+    // everything the providers currently list.
+    pub fn post_predates_route(&self, post_id: &str, provider: &str, chat_id: &str) -> Result<bool, Error> {
+        self.connection.query_row("SELECT EXISTS (SELECT 1 FROM Routes JOIN Posts ON Posts.id = ?1
+            WHERE Routes.provider = ?2 AND Routes.chatId = ?3 AND Routes.createdAt > Posts.handledAt)", [post_id, provider, chat_id], |row| row.get(0)).map_err(|e| e.into())
+    }
+
+    pub fn add_neighbourhood(&self, chat_id: &str, neighbourhood: &str) -> Result<(), Error> {
+        self.connection.execute("INSERT OR IGNORE INTO ChatNeighbourhoods (chatId, neighbourhood, fromConfig) VALUES (?1, ?2, 0)", [chat_id, neighbourhood])?;
+
+        Ok(())
+    }
+
+    // NOTE: Returns whether the neighbourhood was subscribed.
+    pub fn remove_neighbourhood(&self, chat_id: &str, neighbourhood: &str) -> Result<bool, Error> {
+        let removed = self.connection.execute("DELETE FROM ChatNeighbourhoods WHERE chatId = ?1 AND neighbourhood = ?2 AND fromConfig = 0", [chat_id, neighbourhood])?;
+
+        Ok(removed > 0)
+    }
+
+    // NOTE: Only removes what the chat subscribed to by itself. Routes and
+    // neighbourhoods from the config file are kept.
+    pub fn remove_chat_subscriptions(&self, chat_id: &str) -> Result<(), Error> {
+        let transaction = self.connection.unchecked_transaction()?;
+
+        transaction.execute("DELETE FROM Routes WHERE chatId = ?1 AND fromConfig = 0", [chat_id])?;
+        transaction.execute("DELETE FROM ChatNeighbourhoods WHERE chatId = ?1 AND fromConfig = 0", [chat_id])?;
+
+        transaction.commit().map_err(|e| e.into())
+    }
+
+    pub fn delivery_exists(&self, post_id: &str, chat_id: &str) -> Result<bool, Error> {
+        let mut stmt = self.connection.prepare("SELECT postId FROM Deliveries WHERE postId = ?1 AND chatId = ?2")?;
+        let mut rows = stmt.query([post_id, chat_id])?;
+
+        rows.next().map(|r| r.is_some()).map_err(|e| e.into())
+    }
+
+    // NOTE: Returns (chat id, delivered at) of the deliveries already sent.
+    pub fn deliveries_for_post(&self, post_id: &str) -> Result<Vec<(String, String)>, Error> {
+        let mut stmt = self.connection.prepare("SELECT chatId, deliveredAt FROM Deliveries WHERE postId = ?1 AND deliveredAt IS NOT NULL ORDER BY deliveredAt, chatId")?;
+        let rows = stmt.query_map([post_id], |row| Ok((row.get(0)?, row.get(1)?)))?;
+
+        rows.collect::<Result<_, _>>().map_err(|e| e.into())
+    }
+
+    pub fn save_delivery(&self, post_id: &str, chat_id: &str) -> Result<(), Error> {
+        let mut stmt = self.connection.prepare("INSERT OR IGNORE INTO Deliveries (postId, chatId, deliveredAt) VALUES (?1, ?2, datetime('now'))")?;
+        stmt.execute([post_id, chat_id])?;
+
+        Ok(())
+    }
+
+    // NOTE: Keeps the post from being enqueued for the chat again while it
+    // waits in the outbox. deliveredAt is only set once it is sent.
+    pub fn save_pending_delivery(&self, post_id: &str, chat_id: &str) -> Result<(), Error> {
+        let mut stmt = self.connection.prepare("INSERT OR IGNORE INTO Deliveries (postId, chatId) VALUES (?1, ?2)")?;
+        stmt.execute([post_id, chat_id])?;
+
+        Ok(())
+    }
+
+    pub fn message_ids(&self, post_id: &str, chat_id: &str) -> Result<Vec<i64>, Error> {
+        let mut stmt = self.connection.prepare("SELECT messageId FROM DeliveredMessages WHERE postId = ?1 AND chatId = ?2 ORDER BY part")?;
+        let rows = stmt.query_map([post_id, chat_id], |row| row.get(0))?;
+
+        rows.collect::<Result<_, _>>().map_err(|e| e.into())
+    }
+
+    pub fn save_message_ids(&self, post_id: &str, chat_id: &str, message_ids: &[i64]) -> Result<(), Error> {
+        let transaction = self.connection.unchecked_transaction()?;
+
+        transaction.execute("DELETE FROM DeliveredMessages WHERE postId = ?1 AND chatId = ?2", [post_id, chat_id])?;
+        {
+            let mut stmt = transaction.prepare("INSERT INTO DeliveredMessages (postId, chatId, part, messageId) VALUES (?1, ?2, ?3, ?4)")?;
+            for (part, message_id) in message_ids.iter().enumerate() {
+                stmt.execute(rusqlite::params![post_id, chat_id, part, message_id])?;
+            }
+        }
+
+        transaction.commit().map_err(|e| e.into())
+    }
+
+    pub fn save_message_id(&self, post_id: &str, chat_id: &str, part: usize, message_id: i64) -> Result<(), Error> {
+        let mut stmt = self.connection.prepare("INSERT OR REPLACE INTO DeliveredMessages (postId, chatId, part, messageId) VALUES (?1, ?2, ?3, ?4)")?;
+        stmt.execute(rusqlite::params![post_id, chat_id, part, message_id])?;
+
+        Ok(())
+    }
+
+    pub fn enqueue_outbox(&self, post_id: &str, chat_id: &str, kind: OutboxKind, text: &str, fallback_text: Option<&str>) -> Result<(), Error> {
+        let mut stmt = self.connection.prepare("INSERT INTO Outbox (postId, chatId, kind, text, fallbackText, createdAt, updatedAt) VALUES (?1, ?2, ?3, ?4, ?5, datetime('now'), datetime('now'))")?;
+        stmt.execute(rusqlite::params![post_id, chat_id, kind.as_str(), text, fallback_text])?;
+
+        Ok(())
+    }
+
+    // NOTE: Returns the entries still to be delivered, oldest first.
+    pub fn pending_outbox_entries(&self) -> Result<Vec<OutboxEntry>, Error> {
+        let mut stmt = self.connection.prepare("SELECT id, postId, chatId, kind, text, fallbackText, attempts FROM Outbox WHERE state IN ('pending', 'sending') ORDER BY id")?;
+        let rows = stmt.query_map([], |row| {
+            let kind: String = row.get(3)?;
+
+            Ok(OutboxEntry {
+                id: row.get(0)?,
+                post_id: row.get(1)?,
+                chat_id: row.get(2)?,
+                kind: OutboxKind::from_str(&kind).ok_or_else(|| rusqlite::Error::InvalidColumnType(3, "kind".to_string(), rusqlite::types::Type::Text))?,
+                text: row.get(4)?,
+                fallback_text: row.get(5)?,
+                attempts: row.get(6)?,
+            })
+        })?;
+
+        rows.collect::<Result<_, _>>().map_err(|e| e.into())
+    }
+
+    pub fn mark_outbox_sending(&self, id: i64) -> Result<(), Error> {
+        self.connection.execute("UPDATE Outbox SET state = 'sending', updatedAt = datetime('now') WHERE id = ?1", [id])?;
+
+        Ok(())
+    }
+
+    pub fn mark_outbox_sent(&self, id: i64) -> Result<(), Error> {
+        let transaction = self.connection.unchecked_transaction()?;
+
+        transaction.execute("UPDATE Outbox SET state = 'sent', lastError = NULL, updatedAt = datetime('now') WHERE id = ?1", [id])?;
+        transaction.execute("UPDATE Deliveries SET deliveredAt = datetime('now')
+            WHERE (postId, chatId) = (SELECT postId, chatId FROM Outbox WHERE id = ?1 AND kind = 'post')", [id])?;
+
+        transaction.commit().map_err(|e| e.into())
+    }
+
+    // NOTE: Returns the entries of the post that were not sent yet,
+    // including those that ran out of attempts.
+    pub fn unsent_outbox_entries_for_post(&self, post_id: &str) -> Result<Vec<UnsentOutboxEntry>, Error> {
+        let mut stmt = self.connection.prepare("SELECT chatId, kind, state, lastError FROM Outbox WHERE postId = ?1 AND state != 'sent' ORDER BY id")?;
+        let rows = stmt.query_map([post_id], |row| {
+            let kind: String = row.get(1)?;
+
+            Ok(UnsentOutboxEntry {
+                chat_id: row.get(0)?,
+                kind: OutboxKind::from_str(&kind).ok_or_else(|| rusqlite::Error::InvalidColumnType(1, "kind".to_string(), rusqlite::types::Type::Text))?,
+                state: row.get(2)?,
+                last_error: row.get(3)?,
+            })
+        })?;
+
+        rows.collect::<Result<_, _>>().map_err(|e| e.into())
+    }
+
+    // NOTE: The entry is retried later unless it ran out of attempts.
+    pub fn mark_outbox_failed(&self, id: i64, error: &str, max_attempts: u32) -> Result<(), Error> {
+        self.connection.execute("UPDATE Outbox SET
+            attempts = attempts + 1,
+            state = CASE WHEN attempts + 1 >= ?3 THEN 'failed' ELSE 'pending' END,
+            lastError = ?2,
+            updatedAt = datetime('now')
+            WHERE id = ?1", rusqlite::params![id, error, max_attempts])?;
+
+        Ok(())
+    }
+
+    // NOTE: Databases created before per-chat deliveries only know which
+    // posts were handled. Those posts were all sent to a single chat, so
+    // they are recorded as delivered to it the first time this runs.
+    pub fn adopt_legacy_deliveries(&self, chat_id: &str) -> Result<(), Error> {
+        self.connection.execute("INSERT OR IGNORE INTO Deliveries (postId, chatId, deliveredAt)
+            SELECT id, ?1, handledAt FROM Posts
+            WHERE NOT EXISTS (SELECT 1 FROM Deliveries)", [chat_id])?;
+
+        Ok(())
+    }
+
+    // NOTE: Replaces every row that came from the config file, leaving the
+    // ones created by other means untouched.
+    fn sync_config_rows(&self, table: &str, columns: &str, rows: &[(String, String)]) -> Result<(), Error> {
+        let transaction = self.connection.unchecked_transaction()?;
+
+        transaction.execute(&format!("DELETE FROM {} WHERE fromConfig = 1", table), ())?;
+        {
+            let mut stmt = transaction.prepare(&format!("INSERT OR REPLACE INTO {} ({}, fromConfig) VALUES (?1, ?2, 1)", table, columns))?;
+            for (first, second) in rows {
+                stmt.execute([first, second])?;
+            }
+        }
+
+        transaction.commit().map_err(|e| e.into())
+    }
+}
+
+// NOTE: Expects the provider, title, url, content, date and dateHasTime
+// columns, in this order.
+fn post_from_row(row: &Row) -> Result<NewsPost, rusqlite::Error> {
+    let provider: String = row.get(0)?;
+    let date: Option<String> = row.get(4)?;
+    let has_time: Option<bool> = row.get(5)?;
+
+    Ok(NewsPost::new(
+        &provider,
+        row.get(1)?,
+        row.get(2)?,
+        row.get(3)?,
+        date.and_then(|date| DateTime::parse_from_rfc3339(&date).ok()).map(|date| PostDate { date_time: date.with_timezone(&TIMEZONE), has_time: has_time.unwrap_or(false) }),
+    ))
+}
+
+impl OutboxKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            OutboxKind::Post => "post",
+            OutboxKind::Update => "update",
+            OutboxKind::Edit => "edit",
+        }
+    }
+
+    fn from_str(value: &str) -> Option<Self> {
+        match value {
+            "post" => Some(OutboxKind::Post),
+            "update" => Some(OutboxKind::Update),
+            "edit" => Some(OutboxKind::Edit),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use chrono::{NaiveDate, NaiveTime};
+
+    use crate::news_post::{local_date, local_date_time, NewsPost};
+
+    use super::{Database, OutboxKind, UnsentOutboxEntry};
+
+    fn save_legacy_post(database: &Database, id: &str) {
+        database.connection.execute("INSERT INTO Posts (id, date, handledAt) VALUES (?1, NULL, datetime('now'))", [id]).unwrap();
+    }
+
+    #[test]
+    fn tracks_deliveries_per_chat() {
+        let database = Database::new(Path::new(":memory:")).unwrap();
+
+        database.sync_config_routes(&[
+            ("CEDAE".to_string(), "-1".to_string()),
+            ("CEDAE".to_string(), "-2".to_string()),
+        ]).unwrap();
+        database.save_delivery("post", "-1").unwrap();
+
+        assert_eq!(database.chats_for_provider("CEDAE").unwrap(), vec!["-1", "-2"]);
+        assert!(database.delivery_exists("post", "-1").unwrap());
+        assert!(!database.delivery_exists("post", "-2").unwrap());
+    }
+
+    #[test]
+    fn replaces_the_message_ids_of_a_delivery() {
+        let database = Database::new(Path::new(":memory:")).unwrap();
+
+        database.save_message_ids("post", "-1", &[10, 11, 12]).unwrap();
+        database.save_message_ids("post", "-1", &[10, 11]).unwrap();
+
+        assert_eq!(database.message_ids("post", "-1").unwrap(), vec![10, 11]);
+        assert!(database.message_ids("post", "-2").unwrap().is_empty());
+    }
+
+    #[test]
+    fn keeps_config_subscriptions_when_a_chat_unsubscribes() {
+        let database = Database::new(Path::new(":memory:")).unwrap();
+
+        database.sync_config_routes(&[("CEDAE".to_string(), "-1".to_string())]).unwrap();
+        database.add_route("Iguá", "-1").unwrap();
+        database.add_neighbourhood("-1", "Bangu").unwrap();
+
+        database.remove_chat_subscriptions("-1").unwrap();
+
+        assert_eq!(database.providers_for_chat("-1").unwrap(), vec!["CEDAE"]);
+        assert!(database.neighbourhoods_for_chat("-1").unwrap().is_empty());
+    }
+
+    #[test]
+    fn adopts_legacy_posts_only_once() {
+        let database = Database::new(Path::new(":memory:")).unwrap();
+
+        save_legacy_post(&database, "old");
+        database.adopt_legacy_deliveries("-1").unwrap();
+
+        save_legacy_post(&database, "new");
+        database.adopt_legacy_deliveries("-1").unwrap();
+
+        assert!(database.delivery_exists("old", "-1").unwrap());
+        assert!(!database.delivery_exists("new", "-1").unwrap());
+    }
+
+    #[test]
+    fn moves_legacy_posts_to_their_stable_id() {
+        let database = Database::new(Path::new(":memory:")).unwrap();
+
+        save_legacy_post(&database, "content-hash");
+        database.save_delivery("content-hash", "-1").unwrap();
+
+        database.adopt_legacy_post("stable-id", "content-hash").unwrap();
+
+        assert!(database.post_exists("stable-id").unwrap());
+        assert!(!database.post_exists("content-hash").unwrap());
+        assert!(database.delivery_exists("stable-id", "-1").unwrap());
+        assert_eq!(database.latest_revision("stable-id").unwrap(), None);
+
+        database.save_revision("stable-id", "content-hash", "Previsão: 18h.").unwrap();
+        database.save_revision("stable-id", "edited-hash", "Previsão: 22h.").unwrap();
+
+        assert_eq!(database.latest_revision("stable-id").unwrap(), Some(("edited-hash".to_string(), "Previsão: 22h.".to_string())));
+    }
+
+    #[test]
+    fn keeps_outbox_entries_until_sent_or_out_of_attempts() {
+        let database = Database::new(Path::new(":memory:")).unwrap();
+
+        database.enqueue_outbox("post", "-1", OutboxKind::Post, "<b>Obra</b>", None).unwrap();
+        database.enqueue_outbox("post", "-2", OutboxKind::Edit, "<b>Obra</b>", Some("Atualização")).unwrap();
+        database.enqueue_outbox("post", "-3", OutboxKind::Update, "Atualização", None).unwrap();
+
+        let entries = database.pending_outbox_entries().unwrap();
+        assert_eq!(entries.iter().map(|entry| entry.kind).collect::<Vec<_>>(), vec![OutboxKind::Post, OutboxKind::Edit, OutboxKind::Update]);
+        assert_eq!(entries[1].fallback_text.as_deref(), Some("Atualização"));
+
+        // NOTE: An entry left in sending is still pending delivery.
+        database.mark_outbox_sending(entries[0].id).unwrap();
+        database.mark_outbox_sent(entries[1].id).unwrap();
+        database.mark_outbox_failed(entries[2].id, "timeout", 2).unwrap();
+
+        let entries = database.pending_outbox_entries().unwrap();
+        assert_eq!(entries.iter().map(|entry| (entry.chat_id.as_str(), entry.attempts)).collect::<Vec<_>>(), vec![("-1", 0), ("-3", 1)]);
+
+        database.mark_outbox_failed(entries[1].id, "timeout", 2).unwrap();
+
+        assert_eq!(database.pending_outbox_entries().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn sets_the_delivery_time_once_sent() {
+        let database = Database::new(Path::new(":memory:")).unwrap();
+
+        for chat_id in ["-1", "-2"] {
+            database.enqueue_outbox("post", chat_id, OutboxKind::Post, "<b>Obra</b>", None).unwrap();
+            database.save_pending_delivery("post", chat_id).unwrap();
+        }
+
+        let entries = database.pending_outbox_entries().unwrap();
+        database.mark_outbox_sent(entries[0].id).unwrap();
+        database.mark_outbox_failed(entries[1].id, "chat not found", 1).unwrap();
+
+        assert!(database.delivery_exists("post", "-2").unwrap());
+        assert_eq!(database.deliveries_for_post("post").unwrap().into_iter().map(|(chat_id, _)| chat_id).collect::<Vec<_>>(), vec!["-1"]);
+        assert_eq!(database.unsent_outbox_entries_for_post("post").unwrap(), vec![UnsentOutboxEntry {
+            chat_id: "-2".to_string(),
+            kind: OutboxKind::Post,
+            state: "failed".to_string(),
+            last_error: Some("chat not found".to_string()),
+        }]);
+    }
+
+    #[test]
+    fn stores_post_contents() {
+        let database = Database::new(Path::new(":memory:")).unwrap();
+
+        let dated = NewsPost::new("CEDAE", "Obra".to_string(), "https://cedae.com.br/obra".to_string(), "Previsão: 18h.".to_string(), NaiveDate::from_ymd_opt(2024, 10, 14).map(local_date));
+        let edited = NewsPost::new("CEDAE", "Obra".to_string(), "https://cedae.com.br/obra".to_string(), "Previsão: 22h.".to_string(), NaiveDate::from_ymd_opt(2024, 10, 14).map(local_date));
+        let undated = NewsPost::new("Iguá", "Aviso".to_string(), "https://igua.com.br/aviso".to_string(), "Sem data.".to_string(), None);
+
+        database.save_post(&undated).unwrap();
+        database.save_post(&dated).unwrap();
+        database.save_post(&edited).unwrap();
+        save_legacy_post(&database, "legacy");
+
+        assert_eq!(database.latest_posts(10).unwrap(), vec![edited, undated]);
+
+        let midnight = NewsPost::new("Iguá", "Obra".to_string(), "https://igua.com.br/obra".to_string(), "Início: 0h.".to_string(), NaiveDate::from_ymd_opt(2024, 10, 15).map(|date| local_date_time(date.and_time(NaiveTime::MIN))));
+        database.save_post(&midnight).unwrap();
+
+        assert_eq!(database.post(midnight.id()).unwrap(), Some(midnight));
+    }
+
+    #[test]
+    fn searches_posts_ignoring_accents() {
+        let database = Database::new(Path::new(":memory:")).unwrap();
+
+        let water = NewsPost::new("CEDAE", "Falta d'água em Bangu".to_string(), "https://cedae.com.br/falta".to_string(), "Manutenção na rede.".to_string(), NaiveDate::from_ymd_opt(2024, 10, 14).map(local_date));
+        let sewage = NewsPost::new("Iguá", "Obra de esgoto".to_string(), "https://igua.com.br/obra".to_string(), "Sem impacto na água.".to_string(), NaiveDate::from_ymd_opt(2024, 10, 14).map(local_date));
+        let edited = NewsPost::new("Iguá", "Obra de esgoto".to_string(), "https://igua.com.br/obra".to_string(), "Sem impacto no abastecimento.".to_string(), NaiveDate::from_ymd_opt(2024, 10, 14).map(local_date));
+
+        database.save_post(&water).unwrap();
+        database.save_post(&sewage).unwrap();
+
+        assert_eq!(database.search_posts("agua", 10).unwrap(), vec![water.clone(), sewage]);
+        assert_eq!(database.search_posts("manuten bangu", 10).unwrap(), vec![water.clone()]);
+        assert!(database.search_posts("\"*", 10).unwrap().is_empty());
+
+        database.save_post(&edited).unwrap();
+
+        assert_eq!(database.search_posts("água", 10).unwrap(), vec![water]);
+    }
+
+    #[test]
+    fn forgets_posts() {
+        let database = Database::new(Path::new(":memory:")).unwrap();
+        let post = NewsPost::new("CEDAE", "Obra".to_string(), "https://cedae.com.br/obra".to_string(), "Previsão: 18h.".to_string(), None);
+
+        database.save_post(&post).unwrap();
+        database.save_revision(post.id(), post.revision(), post.content()).unwrap();
+        database.save_delivery(post.id(), "-1").unwrap();
+        database.save_message_id(post.id(), "-1", 0, 10).unwrap();
+
+        assert_eq!(database.post(post.id()).unwrap(), Some(post.clone()));
+        assert_eq!(database.deliveries_for_post(post.id()).unwrap().len(), 1);
+
+        assert!(database.forget_post(post.id()).unwrap());
+        assert!(!database.forget_post(post.id()).unwrap());
+
+        assert_eq!(database.post(post.id()).unwrap(), None);
+        assert_eq!(database.latest_revision(post.id()).unwrap(), None);
+        assert!(!database.delivery_exists(post.id(), "-1").unwrap());
+        assert!(database.message_ids(post.id(), "-1").unwrap().is_empty());
+        assert!(database.search_posts("obra", 10).unwrap().is_empty());
+    }
+
+    #[test]
+    fn snapshots_leave_the_file_untouched() {
+        let path = std::env::temp_dir().join(format!("snapshot-test-{}.db", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+
+        let post = |url: &str| NewsPost::new("CEDAE", "Obra".to_string(), url.to_string(), "Previsão: 18h.".to_string(), None);
+
+        Database::new(&path).unwrap().save_post(&post("https://cedae.com.br/1")).unwrap();
+
+        let snapshot = Database::snapshot(&path).unwrap();
+        assert!(snapshot.post_exists(post("https://cedae.com.br/1").id()).unwrap());
+        snapshot.save_post(&post("https://cedae.com.br/2")).unwrap();
+
+        assert!(!Database::new(&path).unwrap().post_exists(post("https://cedae.com.br/2").id()).unwrap());
+        assert!(!Database::snapshot(Path::new("/nonexistent/data.db")).unwrap().post_exists(post("https://cedae.com.br/1").id()).unwrap());
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
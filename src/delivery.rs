@@ -0,0 +1,360 @@
+use chrono::{TimeDelta, Utc};
+
+use crate::{config::{DeliveryConfig, UpdateMode}, database::{Database, OutboxEntry, OutboxKind}, error::Error, news_post::NewsPost, telegram_bot::{self, TelegramBot, TelegramParseMode}};
+
+// NOTE: Expects posts already in delivery order.
+pub async fn deliver_posts(bot: &TelegramBot, database: &Database, posts: Vec<NewsPost>, config: &DeliveryConfig) -> Result<(), Error> {
+    enqueue_posts(database, posts, config)?;
+
+    deliver_pending(bot, database, config.max_attempts).await
+}
+
+// NOTE: Decides what every chat should receive and records it in the outbox,
+// together with the posts and revisions, in a single transaction. Chats that
+// already got a post are only told about it again when its content is edited.
+// The posts of providers without stored posts are seeded instead, when
+// configured to.
+pub fn enqueue_posts(database: &Database, posts: Vec<NewsPost>, config: &DeliveryConfig) -> Result<(), Error> {
+    let providers_to_seed = providers_to_seed(database, &posts, config)?;
+    let (seeded_posts, posts): (Vec<_>, Vec<_>) = posts.into_iter().partition(|post| providers_to_seed.iter().any(|provider| provider == post.provider()));
+
+    if !seeded_posts.is_empty() {
+        seed_posts(database, seeded_posts)?;
+    }
+
+    for post in &posts {
+        database.adopt_legacy_post(post.id(), post.revision())?;
+    }
+
+    let cutoff = config.max_age_days.map(|days| Utc::now() - TimeDelta::days(days.into()));
+
+    let transaction = database.transaction()?;
+
+    for post in posts {
+        let previous_revision = database.latest_revision(post.id())?;
+        let update_message = match &previous_revision {
+            Some((revision, content)) if revision != post.revision() => post.as_update_html_string(content),
+            _ => None,
+        };
+        let too_old = matches!((cutoff, post.date()), (Some(cutoff), Some(date)) if *date < cutoff);
+
+        for chat_id in database.chats_for_provider(post.provider())? {
+            enqueue_for_chat(database, &post, &chat_id, update_message.as_deref(), config.on_update, too_old)?;
+        }
+
+        save_post(database, &post, previous_revision)?;
+    }
+
+    transaction.commit().map_err(|e| e.into())
+}
+
+// NOTE: A provider is seeded until it has stored posts, so one that failed
+// on the first run does not flood its chats once it recovers.
+pub fn providers_to_seed(database: &Database, posts: &[NewsPost], config: &DeliveryConfig) -> Result<Vec<String>, Error> {
+    let mut providers = vec![];
+
+    if !config.seed_on_first_run {
+        return Ok(providers);
+    }
+
+    for post in posts {
+        if !providers.iter().any(|provider| provider == post.provider()) && !database.provider_has_posts(post.provider())? {
+            providers.push(post.provider().to_string());
+        }
+    }
+
+    Ok(providers)
+}
+
+// NOTE: Marks the posts as delivered to every chat routed to their
+// providers, without sending anything. Their later edits are still sent.
+pub fn seed_posts(database: &Database, posts: Vec<NewsPost>) -> Result<(), Error> {
+    for post in &posts {
+        database.adopt_legacy_post(post.id(), post.revision())?;
+    }
+
+    let transaction = database.transaction()?;
+
+    for post in posts {
+        for chat_id in database.chats_for_provider(post.provider())? {
+            database.save_delivery(post.id(), &chat_id)?;
+        }
+
+        save_post(database, &post, database.latest_revision(post.id())?)?;
+    }
+
+    transaction.commit().map_err(|e| e.into())
+}
+
+// NOTE: Stores the posts that are not stored yet as if they had been
+// delivered. Stored posts are left untouched, so their edits are still
+// announced by the next run. Returns how many posts were stored.
+pub fn backfill_posts(database: &Database, posts: Vec<NewsPost>) -> Result<usize, Error> {
+    let mut new_posts = vec![];
+
+    for post in posts {
+        database.adopt_legacy_post(post.id(), post.revision())?;
+
+        if !database.post_exists(post.id())? {
+            new_posts.push(post);
+        }
+    }
+
+    let count = new_posts.len();
+    seed_posts(database, new_posts)?;
+
+    Ok(count)
+}
+
+// NOTE: Sends the post as a new message to every chat routed to its
+// provider, whether or not it was delivered there before. Later edits
+// replace the new messages.
+pub async fn resend_post(bot: &TelegramBot, database: &Database, post: &NewsPost, config: &DeliveryConfig) -> Result<(), Error> {
+    for chat_id in database.chats_for_provider(post.provider())? {
+        if !wants_post(database, post, &chat_id)? {
+            continue;
+        }
+
+        database.save_message_ids(post.id(), &chat_id, &[])?;
+        database.enqueue_outbox(post.id(), &chat_id, OutboxKind::Post, &post.as_html_string(), None)?;
+        database.save_pending_delivery(post.id(), &chat_id)?;
+    }
+
+    deliver_pending(bot, database, config.max_attempts).await
+}
+
+// NOTE: Delivers everything waiting in the outbox, including what was left
+// by failed or interrupted runs. An entry that cannot be delivered does not
+// stop the others.
+pub async fn deliver_pending(bot: &TelegramBot, database: &Database, max_attempts: u32) -> Result<(), Error> {
+    let mut failures = vec![];
+
+    for entry in database.pending_outbox_entries()? {
+        database.mark_outbox_sending(entry.id)?;
+
+        match deliver_entry(bot, database, &entry).await {
+            Ok(()) => database.mark_outbox_sent(entry.id)?,
+            Err(error) => {
+                database.mark_outbox_failed(entry.id, &error.to_string(), max_attempts)?;
+                failures.push((entry.chat_id, error));
+            },
+        }
+    }
+
+    if !failures.is_empty() {
+        return Err(Error::DeliveriesFailed(failures));
+    }
+
+    Ok(())
+}
+
+fn save_post(database: &Database, post: &NewsPost, previous_revision: Option<(String, String)>) -> Result<(), Error> {
+    if previous_revision.is_none_or(|(revision, _)| revision != post.revision()) {
+        database.save_revision(post.id(), post.revision(), post.content())?;
+    }
+
+    database.save_post(post)
+}
+
+fn enqueue_for_chat(database: &Database, post: &NewsPost, chat_id: &str, update_message: Option<&str>, on_update: UpdateMode, too_old: bool) -> Result<(), Error> {
+    if database.delivery_exists(post.id(), chat_id)? {
+        let Some(update_message) = update_message else {
+            return Ok(());
+        };
+
+        // NOTE: When only editing, the update message is kept as a fallback
+        // for posts whose messages cannot be edited.
+        match on_update {
+            UpdateMode::Edit => database.enqueue_outbox(post.id(), chat_id, OutboxKind::Edit, &post.as_html_string(), Some(update_message))?,
+            UpdateMode::New => database.enqueue_outbox(post.id(), chat_id, OutboxKind::Update, update_message, None)?,
+            UpdateMode::Both => {
+                database.enqueue_outbox(post.id(), chat_id, OutboxKind::Edit, &post.as_html_string(), None)?;
+                database.enqueue_outbox(post.id(), chat_id, OutboxKind::Update, update_message, None)?;
+            },
+        }
+
+        return Ok(());
+    }
+
+    if too_old || database.post_predates_route(post.id(), post.provider(), chat_id)? || !wants_post(database, post, chat_id)? {
+        return Ok(());
+    }
+
+    database.enqueue_outbox(post.id(), chat_id, OutboxKind::Post, &post.as_html_string(), None)?;
+    database.save_pending_delivery(post.id(), chat_id)
+}
+
+fn wants_post(database: &Database, post: &NewsPost, chat_id: &str) -> Result<bool, Error> {
+    let neighbourhoods = database.neighbourhoods_for_chat(chat_id)?;
+
+    Ok(neighbourhoods.is_empty() || post.places().mentions_any(&neighbourhoods))
+}
+
+async fn deliver_entry(bot: &TelegramBot, database: &Database, entry: &OutboxEntry) -> Result<(), Error> {
+    match entry.kind {
+        OutboxKind::Post => {
+            // NOTE: The id of every part is saved as soon as it is sent, so
+            // an interrupted delivery resumes from the first missing part.
+            // Only a part sent right before a crash can be sent twice.
+            let sent_parts = database.message_ids(&entry.post_id, &entry.chat_id)?.len();
+            let parts = telegram_bot::split_message(&entry.text, &TelegramParseMode::Html);
+
+            for (part, text) in parts.into_iter().enumerate().skip(sent_parts) {
+                let message_id = bot.send_part(text, &entry.chat_id, &TelegramParseMode::Html).await?;
+                database.save_message_id(&entry.post_id, &entry.chat_id, part, message_id)?;
+            }
+        },
+        OutboxKind::Update => {
+            bot.send_message(&entry.text, &entry.chat_id, TelegramParseMode::Html).await?;
+        },
+        // NOTE: Posts delivered before message ids were stored cannot be
+        // edited.
+        OutboxKind::Edit => {
+            let message_ids = database.message_ids(&entry.post_id, &entry.chat_id)?;
+
+            if !message_ids.is_empty() {
+                let message_ids = bot.edit_message(&entry.text, &entry.chat_id, &message_ids, TelegramParseMode::Html).await?;
+                database.save_message_ids(&entry.post_id, &entry.chat_id, &message_ids)?;
+            } else if let Some(fallback_text) = &entry.fallback_text {
+                bot.send_message(fallback_text, &entry.chat_id, TelegramParseMode::Html).await?;
+            }
+        },
+    }
+
+    Ok(())
+}
+
+pub async fn report_error(bot: &TelegramBot, owner_chat_id: &str, error: &Error) -> Result<(), Error> {
+    bot.send_message("<b>Error running bot:</b> <i>Comunicados Aguas do Rio</i>", owner_chat_id, TelegramParseMode::Html).await?;
+    bot.send_message(&error.to_string(), owner_chat_id, TelegramParseMode::PlainText).await?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use chrono::{TimeDelta, Utc};
+
+    use crate::{config::DeliveryConfig, database::{Database, OutboxKind}, news_post::{NewsPost, PostDate, TIMEZONE}};
+
+    use super::{backfill_posts, enqueue_posts};
+
+    fn post(content: &str) -> NewsPost {
+        NewsPost::new("CEDAE", "Obra em Bangu".to_string(), "https://cedae.com.br/obra".to_string(), content.to_string(), None)
+    }
+
+    fn dated_post(url: &str, days_ago: i64) -> NewsPost {
+        NewsPost::new("CEDAE", "Obra em Bangu".to_string(), url.to_string(), "Previsão: 18h.".to_string(), Some(PostDate { date_time: (Utc::now() - TimeDelta::days(days_ago)).with_timezone(&TIMEZONE), has_time: true }))
+    }
+
+    fn database() -> Database {
+        let database = Database::new(Path::new(":memory:")).unwrap();
+        database.sync_config_routes(&[("CEDAE".to_string(), "-1".to_string())]).unwrap();
+
+        database
+    }
+
+    #[test]
+    fn enqueues_new_and_edited_posts_once() {
+        let database = database();
+        let config = DeliveryConfig::default();
+
+        enqueue_posts(&database, vec![post("Previsão: 18h.")], &config).unwrap();
+        enqueue_posts(&database, vec![post("Previsão: 18h.")], &config).unwrap();
+
+        let entries = database.pending_outbox_entries().unwrap();
+        assert_eq!(entries.iter().map(|entry| entry.kind).collect::<Vec<_>>(), vec![OutboxKind::Post]);
+        database.mark_outbox_sent(entries[0].id).unwrap();
+
+        enqueue_posts(&database, vec![post("Previsão: 22h.")], &config).unwrap();
+        enqueue_posts(&database, vec![post("Previsão: 22h.")], &config).unwrap();
+
+        let entries = database.pending_outbox_entries().unwrap();
+        assert_eq!(entries.iter().map(|entry| entry.kind).collect::<Vec<_>>(), vec![OutboxKind::Edit, OutboxKind::Update]);
+        assert!(entries[1].text.contains("➕ Previsão: 22h."));
+    }
+
+    #[test]
+    fn seeds_the_first_run() {
+        let database = database();
+        let config = DeliveryConfig { seed_on_first_run: true, ..DeliveryConfig::default() };
+
+        enqueue_posts(&database, vec![dated_post("https://cedae.com.br/1", 100)], &config).unwrap();
+        assert!(database.pending_outbox_entries().unwrap().is_empty());
+
+        enqueue_posts(&database, vec![dated_post("https://cedae.com.br/1", 100), dated_post("https://cedae.com.br/2", 0)], &config).unwrap();
+        let entries = database.pending_outbox_entries().unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].post_id, dated_post("https://cedae.com.br/2", 0).id());
+    }
+
+    #[test]
+    fn seeds_providers_that_failed_on_the_first_run() {
+        let database = database();
+        database.sync_config_routes(&[("CEDAE".to_string(), "-1".to_string()), ("Iguá".to_string(), "-1".to_string())]).unwrap();
+        let config = DeliveryConfig { seed_on_first_run: true, ..DeliveryConfig::default() };
+        let igua_post = |url: &str| NewsPost::new("Iguá", "Obra em Bangu".to_string(), url.to_string(), "Previsão: 18h.".to_string(), None);
+
+        enqueue_posts(&database, vec![dated_post("https://cedae.com.br/1", 100)], &config).unwrap();
+        enqueue_posts(&database, vec![dated_post("https://cedae.com.br/2", 0), igua_post("https://igua.com.br/1")], &config).unwrap();
+
+        let entries = database.pending_outbox_entries().unwrap();
+        assert_eq!(entries.into_iter().map(|entry| entry.post_id).collect::<Vec<_>>(), vec![dated_post("https://cedae.com.br/2", 0).id().to_string()]);
+
+        enqueue_posts(&database, vec![igua_post("https://igua.com.br/1"), igua_post("https://igua.com.br/2")], &config).unwrap();
+
+        let entries = database.pending_outbox_entries().unwrap();
+        assert_eq!(entries.last().unwrap().post_id, igua_post("https://igua.com.br/2").id());
+        assert_eq!(entries.len(), 2);
+    }
+
+    #[test]
+    fn backfills_only_posts_not_stored_yet() {
+        let database = database();
+        let config = DeliveryConfig::default();
+
+        enqueue_posts(&database, vec![post("Previsão: 18h.")], &config).unwrap();
+        for entry in database.pending_outbox_entries().unwrap() {
+            database.mark_outbox_sent(entry.id).unwrap();
+        }
+
+        assert_eq!(backfill_posts(&database, vec![post("Previsão: 22h."), dated_post("https://cedae.com.br/antiga", 400)]).unwrap(), 1);
+        assert!(database.pending_outbox_entries().unwrap().is_empty());
+
+        enqueue_posts(&database, vec![post("Previsão: 22h."), dated_post("https://cedae.com.br/antiga", 400)], &config).unwrap();
+
+        let entries = database.pending_outbox_entries().unwrap();
+        assert_eq!(entries.iter().map(|entry| entry.kind).collect::<Vec<_>>(), vec![OutboxKind::Edit, OutboxKind::Update]);
+    }
+
+    #[test]
+    fn skips_posts_older_than_the_max_age() {
+        let database = database();
+        let config = DeliveryConfig { max_age_days: Some(30), ..DeliveryConfig::default() };
+
+        enqueue_posts(&database, vec![dated_post("https://cedae.com.br/1", 31), dated_post("https://cedae.com.br/2", 29), post("Previsão: 18h.")], &config).unwrap();
+
+        let post_ids = database.pending_outbox_entries().unwrap().into_iter().map(|entry| entry.post_id).collect::<Vec<_>>();
+        assert_eq!(post_ids, vec![dated_post("https://cedae.com.br/2", 29).id().to_string(), post("Previsão: 18h.").id().to_string()]);
+    }
+
+    #[test]
+    fn sends_new_subscribers_only_later_posts() {
+        let database = database();
+        let config = DeliveryConfig::default();
+
+        enqueue_posts(&database, vec![dated_post("https://cedae.com.br/1", 1)], &config).unwrap();
+        for entry in database.pending_outbox_entries().unwrap() {
+            database.mark_outbox_sent(entry.id).unwrap();
+        }
+
+        database.add_route("CEDAE", "-2").unwrap();
+        enqueue_posts(&database, vec![dated_post("https://cedae.com.br/1", 1), dated_post("https://cedae.com.br/2", 0)], &config).unwrap();
+
+        let entries = database.pending_outbox_entries().unwrap().into_iter().map(|entry| (entry.post_id, entry.chat_id)).collect::<Vec<_>>();
+        let new_post_id = dated_post("https://cedae.com.br/2", 0).id().to_string();
+        assert_eq!(entries, vec![(new_post_id.clone(), "-1".to_string()), (new_post_id, "-2".to_string())]);
+    }
+}
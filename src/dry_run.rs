@@ -0,0 +1,127 @@
+use std::{fs, path::Path};
+
+use crate::{config::DeliveryConfig, database::{Database, OutboxEntry}, delivery, error::Error, news_post::NewsPost, telegram_bot::{self, TelegramParseMode}};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PostStatus {
+    New,
+    Edited,
+    Seen,
+}
+
+impl PostStatus {
+    fn as_str(&self) -> &'static str {
+        match self {
+            PostStatus::New => "new",
+            PostStatus::Edited => "edited",
+            PostStatus::Seen => "already seen",
+        }
+    }
+}
+
+// NOTE: Expects a snapshot of the database, since the posts are enqueued in
+// it exactly as a real run would. The messages left in its outbox are then
+// rendered instead of sent, including those left by failed runs, which a
+// real run would also retry. Parts are printed, or written to the output
+// directory with one file per part.
+pub fn run(database: &Database, posts: Vec<NewsPost>, config: &DeliveryConfig, output: Option<&Path>) -> Result<(), Error> {
+    let mut statuses = vec![];
+
+    for post in &posts {
+        let status = post_status(database, post)?;
+        statuses.push(status);
+
+        println!("[{}] {} {}: {}", status.as_str(), post.id(), post.provider(), post.title());
+    }
+
+    let providers_to_seed = delivery::providers_to_seed(database, &posts, config)?;
+    if !providers_to_seed.is_empty() {
+        println!("\nThere are no posts of {} yet, so theirs would be marked as delivered instead of sent.", providers_to_seed.join(", "));
+    }
+
+    delivery::enqueue_posts(database, posts, config)?;
+
+    let entries = database.pending_outbox_entries()?;
+    if let Some(output) = output {
+        fs::create_dir_all(output)?;
+    }
+
+    for (index, entry) in entries.iter().enumerate() {
+        let parts = telegram_bot::split_message(&entry.text, &TelegramParseMode::Html);
+
+        for (part, text) in parts.iter().enumerate() {
+            match output {
+                Some(output) => fs::write(output.join(part_file_name(index, entry, part)), text)?,
+                None => println!("\n=== {} to chat {}, part {}/{}\n{}", entry.kind.as_str(), entry.chat_id, part + 1, parts.len(), text),
+            }
+        }
+    }
+
+    let counts = [PostStatus::New, PostStatus::Edited, PostStatus::Seen]
+        .iter()
+        .map(|status| format!("{} {}", statuses.iter().filter(|counted| *counted == status).count(), status.as_str()))
+        .collect::<Vec<_>>();
+    println!("\n{} post(s): {}. {} message(s) would be sent.", statuses.len(), counts.join(", "), entries.len());
+
+    if let Some(output) = output {
+        println!("Messages written to {}", output.display());
+    }
+
+    Ok(())
+}
+
+// NOTE: Posts stored under their legacy content hash count as seen.
+pub fn post_status(database: &Database, post: &NewsPost) -> Result<PostStatus, Error> {
+    match database.latest_revision(post.id())? {
+        Some((revision, _)) if revision != post.revision() => Ok(PostStatus::Edited),
+        Some(_) => Ok(PostStatus::Seen),
+        None if database.post_exists(post.id())? || database.post_exists(post.revision())? => Ok(PostStatus::Seen),
+        None => Ok(PostStatus::New),
+    }
+}
+
+fn part_file_name(index: usize, entry: &OutboxEntry, part: usize) -> String {
+    format!("{:03}-{}-chat{}-part{}.html", index + 1, entry.kind.as_str(), entry.chat_id, part + 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use crate::{config::DeliveryConfig, database::Database, news_post::NewsPost};
+
+    use super::{post_status, run, PostStatus};
+
+    fn post(url: &str, content: &str) -> NewsPost {
+        NewsPost::new("CEDAE", "Obra em Bangu".to_string(), url.to_string(), content.to_string(), None)
+    }
+
+    #[test]
+    fn renders_new_and_edited_posts() {
+        let database = Database::new(Path::new(":memory:")).unwrap();
+        database.sync_config_routes(&[("CEDAE".to_string(), "-1".to_string())]).unwrap();
+
+        let seen = post("https://cedae.com.br/1", "Previsão: 18h.");
+        let edited = post("https://cedae.com.br/2", "Previsão: 18h.");
+        crate::delivery::enqueue_posts(&database, vec![seen.clone(), edited.clone()], &DeliveryConfig::default()).unwrap();
+        for entry in database.pending_outbox_entries().unwrap() {
+            database.mark_outbox_sent(entry.id).unwrap();
+        }
+
+        let edited = post("https://cedae.com.br/2", "Previsão: 22h.");
+        let new = post("https://cedae.com.br/3", "Previsão: 18h.");
+
+        assert_eq!(post_status(&database, &seen).unwrap(), PostStatus::Seen);
+        assert_eq!(post_status(&database, &edited).unwrap(), PostStatus::Edited);
+        assert_eq!(post_status(&database, &new).unwrap(), PostStatus::New);
+
+        let output = std::env::temp_dir().join(format!("dry-run-test-{}", std::process::id()));
+        run(&database, vec![seen, edited, new], &DeliveryConfig::default(), Some(&output)).unwrap();
+
+        let mut files = std::fs::read_dir(&output).unwrap().map(|file| file.unwrap().file_name().into_string().unwrap()).collect::<Vec<_>>();
+        files.sort();
+        assert_eq!(files, vec!["001-edit-chat-1-part1.html", "002-update-chat-1-part1.html", "003-post-chat-1-part1.html"]);
+
+        std::fs::remove_dir_all(&output).unwrap();
+    }
+}
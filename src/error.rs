@@ -1,12 +1,24 @@
 use std::fmt::Display;
 
 #[derive(Debug)]
+#[allow(clippy::enum_variant_names)]
 pub enum Error {
     ConnectionError(reqwest::Error),
+    RequestFailed { url: reqwest::Url, attempts: u32, error: reqwest::Error },
+    JsonParseError(serde_json::Error),
+    FixtureNotFound(String),
+    IoError(std::io::Error),
+    ConfigError(String),
     ElementNotFound(&'static str),
     AttrNotFound(&'static str),
     TelegramApiError(telegram_bot_api::bot::APIResponseError),
     DatabaseConnectionError(rusqlite::Error),
+    UnsupportedDatabaseVersion(usize),
+    PostNotFound(String),
+    UnknownProvider(String),
+    ScrapersFailed(Vec<(String, Error)>),
+    DeliveriesFailed(Vec<(String, Error)>),
+    RunFailed(Vec<Error>),
 }
 
 impl From<reqwest::Error> for Error {
@@ -15,6 +27,18 @@ impl From<reqwest::Error> for Error {
     }
 }
 
+impl From<serde_json::Error> for Error {
+    fn from(value: serde_json::Error) -> Self {
+        Self::JsonParseError(value)
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(value: std::io::Error) -> Self {
+        Self::IoError(value)
+    }
+}
+
 impl From<telegram_bot_api::bot::APIResponseError> for Error {
     fn from(value: telegram_bot_api::bot::APIResponseError) -> Self {
         Self::TelegramApiError(value)
@@ -33,6 +57,21 @@ impl Display for Error {
             Error::ConnectionError(error) => {
                 writeln!(f, "Reqwest Connection Error: {:#?}", error)
             },
+            Error::RequestFailed { url, attempts, error } => {
+                writeln!(f, "Request to {} failed after {} attempt(s): {:#?}", url, attempts, error)
+            },
+            Error::JsonParseError(error) => {
+                writeln!(f, "JSON Parse Error: {:#?}", error)
+            },
+            Error::FixtureNotFound(url) => {
+                writeln!(f, "No fixture found for \"{}\"", url)
+            },
+            Error::IoError(error) => {
+                writeln!(f, "IO Error: {:#?}", error)
+            },
+            Error::ConfigError(message) => {
+                writeln!(f, "Invalid configuration:\n{}", message)
+            },
             Error::ElementNotFound(element_name) => {
                 writeln!(f, "Element \"{}\" not found while parsing website", element_name)
             },
@@ -45,6 +84,40 @@ impl Display for Error {
             Error::DatabaseConnectionError(error) => {
                 writeln!(f, "SQLite Connection Error: {:#?}", error)
             },
+            Error::UnsupportedDatabaseVersion(version) => {
+                writeln!(f, "The database schema (version {}) is newer than this version of the bot supports", version)
+            },
+            Error::PostNotFound(id) => {
+                writeln!(f, "No post with id \"{}\"", id)
+            },
+            Error::UnknownProvider(provider) => {
+                writeln!(f, "Unknown or disabled provider \"{}\"", provider)
+            },
+            Error::ScrapersFailed(failures) => {
+                writeln!(f, "{} scraper(s) failed:", failures.len())?;
+
+                for (scraper_name, error) in failures {
+                    write!(f, "\n[{}] {}", scraper_name, error)?;
+                }
+
+                Ok(())
+            },
+            Error::DeliveriesFailed(failures) => {
+                writeln!(f, "{} delivery(ies) failed:", failures.len())?;
+
+                for (chat_id, error) in failures {
+                    write!(f, "\n[chat {}] {}", chat_id, error)?;
+                }
+
+                Ok(())
+            },
+            Error::RunFailed(errors) => {
+                for error in errors {
+                    writeln!(f, "{}", error)?;
+                }
+
+                Ok(())
+            },
         }
     }
 }
\ No newline at end of file
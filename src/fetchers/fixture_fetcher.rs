@@ -0,0 +1,51 @@
+use std::{collections::HashMap, fs, path::PathBuf};
+
+use async_trait::async_trait;
+use reqwest::Url;
+
+use crate::error::Error;
+
+use super::PageFetcher;
+
+pub const INDEX_FILE_NAME: &str = "index.txt";
+
+// NOTE: Serves pages previously saved to a directory instead of fetching
+// them from the network. The directory must contain an index file where
+// each line holds a file name and the URL it answers for, separated by
+// whitespace. Empty lines and lines starting with '#' are ignored.
+pub struct FixtureFetcher {
+    directory: PathBuf,
+    files_by_url: HashMap<String, String>,
+}
+
+#[async_trait(?Send)]
+impl PageFetcher for FixtureFetcher {
+    async fn fetch(&self, url: Url) -> Result<String, Error> {
+        let file_name = self.files_by_url.get(url.as_str()).ok_or_else(|| Error::FixtureNotFound(url.to_string()))?;
+        let data = fs::read_to_string(self.directory.join(file_name))?;
+
+        Ok(data)
+    }
+}
+
+impl FixtureFetcher {
+    pub fn new(directory: impl Into<PathBuf>) -> Result<Self, Error> {
+        let directory = directory.into();
+        let index = fs::read_to_string(directory.join(INDEX_FILE_NAME))?;
+
+        let files_by_url = index
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .filter_map(|line| {
+                let (file_name, url) = line.split_once(char::is_whitespace)?;
+                Some((url.trim().to_string(), file_name.to_string()))
+            })
+            .collect();
+
+        Ok(Self {
+            directory,
+            files_by_url,
+        })
+    }
+}
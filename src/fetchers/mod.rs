@@ -0,0 +1,12 @@
+use async_trait::async_trait;
+use reqwest::Url;
+
+use crate::error::Error;
+
+pub mod fixture_fetcher;
+pub mod recording_fetcher;
+
+#[async_trait(?Send)]
+pub trait PageFetcher: Send + Sync {
+    async fn fetch(&self, url: Url) -> Result<String, Error>;
+}
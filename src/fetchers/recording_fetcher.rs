@@ -0,0 +1,87 @@
+use std::{fs::{self, OpenOptions}, io::Write, path::PathBuf, sync::{Arc, Mutex}};
+
+use async_trait::async_trait;
+use reqwest::Url;
+
+use crate::error::Error;
+
+use super::{fixture_fetcher::INDEX_FILE_NAME, PageFetcher};
+
+// NOTE: Forwards every request to the inner fetcher and saves the response
+// body to a directory, in the same layout FixtureFetcher reads from.
+pub struct RecordingFetcher {
+    inner: Arc<dyn PageFetcher>,
+
+    directory: PathBuf,
+    recorded_pages: Mutex<usize>,
+}
+
+#[async_trait(?Send)]
+impl PageFetcher for RecordingFetcher {
+    async fn fetch(&self, url: Url) -> Result<String, Error> {
+        let data = self.inner.fetch(url.clone()).await?;
+        self.record(&url, &data)?;
+
+        Ok(data)
+    }
+}
+
+impl RecordingFetcher {
+    pub fn new(inner: Arc<dyn PageFetcher>, directory: impl Into<PathBuf>) -> Result<Self, Error> {
+        let directory = directory.into();
+
+        fs::create_dir_all(&directory)?;
+        fs::write(directory.join(INDEX_FILE_NAME), "")?;
+
+        Ok(Self {
+            inner,
+
+            directory,
+            recorded_pages: Mutex::new(0),
+        })
+    }
+
+    pub fn recorded_pages(&self) -> usize {
+        *self.recorded_pages.lock().expect("Recorded pages lock poisoned")
+    }
+
+    fn record(&self, url: &Url, data: &str) -> Result<(), Error> {
+        let mut recorded_pages = self.recorded_pages.lock().expect("Recorded pages lock poisoned");
+        let file_name = format!("page-{:03}.html", *recorded_pages + 1);
+
+        fs::write(self.directory.join(&file_name), data)?;
+
+        let mut index = OpenOptions::new().append(true).open(self.directory.join(INDEX_FILE_NAME))?;
+        writeln!(index, "{} {}", file_name, url)?;
+
+        *recorded_pages += 1;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs};
+
+    use reqwest::Url;
+
+    use crate::{fetchers::{fixture_fetcher::FixtureFetcher, PageFetcher}, scrapers::fixture_fetcher};
+
+    use super::RecordingFetcher;
+
+    #[tokio::test]
+    async fn recorded_pages_can_be_replayed() {
+        let directory = env::temp_dir().join(format!("recording-fetcher-test-{}", std::process::id()));
+        let url = Url::parse("https://cedae.com.br/Noticias/").unwrap();
+
+        let recording_fetcher = RecordingFetcher::new(fixture_fetcher("cedae"), &directory).unwrap();
+        let recorded = recording_fetcher.fetch(url.clone()).await.unwrap();
+
+        let replayed = FixtureFetcher::new(&directory).unwrap().fetch(url).await.unwrap();
+        fs::remove_dir_all(&directory).unwrap();
+
+        assert_eq!(recording_fetcher.recorded_pages(), 1);
+        assert_eq!(recorded, replayed);
+    }
+}
@@ -0,0 +1,218 @@
+use std::collections::{BTreeSet, HashMap};
+
+use lazy_static::lazy_static;
+use regex::Regex;
+
+const MUNICIPALITIES: &[&str] = &[
+    "Rio de Janeiro", "Niterói", "São Gonçalo", "Duque de Caxias", "Nova Iguaçu", "Belford Roxo",
+    "São João de Meriti", "Nilópolis", "Mesquita", "Queimados", "Japeri", "Paracambi", "Seropédica",
+    "Itaguaí", "Magé", "Guapimirim", "Itaboraí", "Tanguá", "Maricá", "Rio Bonito", "Cabo Frio",
+    "Arraial do Cabo", "Armação dos Búzios", "São Pedro da Aldeia", "Iguaba Grande", "Araruama", "Saquarema",
+];
+
+// NOTE: Some neighbourhood names exist in more than one municipality (e.g.
+// "Centro"). A mention of such a name tags every municipality that has it.
+const NEIGHBOURHOODS: &[(&str, &[&str])] = &[
+    ("Rio de Janeiro", &[
+        // Centro and Zona Portuária
+        "Centro", "Gamboa", "Santo Cristo", "Saúde", "Lapa", "Santa Teresa", "Rio Comprido", "Cidade Nova",
+        "Estácio", "Catumbi", "Caju", "Paquetá", "Vasco da Gama", "Mangueira", "Benfica", "São Cristóvão",
+        // Zona Sul
+        "Botafogo", "Catete", "Copacabana", "Cosme Velho", "Flamengo", "Gávea", "Glória", "Humaitá", "Ipanema",
+        "Jardim Botânico", "Lagoa", "Laranjeiras", "Leblon", "Leme", "Rocinha", "São Conrado", "Urca", "Vidigal",
+        // Grande Tijuca
+        "Tijuca", "Alto da Boa Vista", "Andaraí", "Grajaú", "Maracanã", "Praça da Bandeira", "Vila Isabel",
+        // Zona Norte
+        "Abolição", "Água Santa", "Cachambi", "Del Castilho", "Encantado", "Engenho de Dentro", "Engenho Novo",
+        "Higienópolis", "Jacaré", "Jacarezinho", "Lins de Vasconcelos", "Maria da Graça", "Méier", "Piedade",
+        "Pilares", "Riachuelo", "Rocha", "Sampaio", "São Francisco Xavier", "Todos os Santos", "Inhaúma",
+        "Engenho da Rainha", "Tomás Coelho", "Complexo do Alemão", "Bonsucesso", "Manguinhos", "Maré", "Olaria",
+        "Ramos", "Penha", "Penha Circular", "Brás de Pina", "Cordovil", "Parada de Lucas", "Vigário Geral",
+        "Jardim América", "Irajá", "Vista Alegre", "Vila da Penha", "Vicente de Carvalho", "Vila Kosmos",
+        "Colégio", "Madureira", "Campinho", "Cascadura", "Cavalcanti", "Engenheiro Leal", "Honório Gurgel",
+        "Marechal Hermes", "Oswaldo Cruz", "Quintino Bocaiúva", "Rocha Miranda", "Turiaçu", "Vaz Lobo",
+        "Bento Ribeiro", "Guadalupe", "Anchieta", "Parque Anchieta", "Ricardo de Albuquerque", "Costa Barros",
+        "Pavuna", "Acari", "Coelho Neto", "Barros Filho", "Parque Colúmbia", "Deodoro", "Vila Militar",
+        "Campo dos Afonsos", "Jardim Sulacap", "Magalhães Bastos",
+        // Ilha do Governador
+        "Ilha do Governador", "Bancários", "Cacuia", "Cocotá", "Freguesia", "Galeão", "Jardim Carioca",
+        "Jardim Guanabara", "Moneró", "Pitangueiras", "Portuguesa", "Praia da Bandeira", "Ribeira", "Tauá",
+        "Zumbi", "Cidade Universitária",
+        // Zona Oeste
+        "Jacarepaguá", "Anil", "Cidade de Deus", "Curicica", "Gardênia Azul", "Pechincha", "Praça Seca",
+        "Tanque", "Taquara", "Vila Valqueire", "Barra da Tijuca", "Camorim", "Grumari", "Itanhangá", "Joá",
+        "Recreio dos Bandeirantes", "Vargem Grande", "Vargem Pequena", "Barra de Guaratiba", "Guaratiba",
+        "Pedra de Guaratiba", "Realengo", "Padre Miguel", "Bangu", "Senador Camará", "Gericinó", "Campo Grande",
+        "Cosmos", "Inhoaíba", "Santíssimo", "Senador Vasconcelos", "Santa Cruz", "Paciência", "Sepetiba",
+    ]),
+    ("Niterói", &[
+        "Centro", "Icaraí", "Ingá", "São Francisco", "Charitas", "Santa Rosa", "Fonseca", "Barreto", "Engenhoca",
+        "Piratininga", "Itaipu", "Camboinhas", "Itacoatiara", "Jurujuba", "Pendotiba", "Largo da Batalha",
+        "Vital Brazil", "São Domingos", "Boa Viagem", "Gragoatá", "Cubango", "Maria Paula", "Santana",
+        "Ponta d'Areia", "Várzea das Moças", "Engenho do Mato", "Badu", "Sapê", "Matapaca",
+    ]),
+    ("São Gonçalo", &[
+        "Centro", "Alcântara", "Neves", "Paraíso", "Colubandê", "Jardim Catarina", "Trindade", "Porto da Pedra",
+        "Mutuá", "Zé Garoto",
+    ]),
+    ("Duque de Caxias", &[
+        "Centro", "Jardim Primavera", "Saracuruna", "Imbariê", "Xerém", "Vila São Luiz", "Gramacho", "Parque Lafaiete",
+    ]),
+    ("Nova Iguaçu", &[
+        "Centro", "Austin", "Comendador Soares", "Cabuçu", "Miguel Couto", "Posse", "Vila de Cava",
+    ]),
+    ("Belford Roxo", &[
+        "Centro", "Heliópolis", "Lote XV", "Areia Branca",
+    ]),
+    ("São João de Meriti", &[
+        "Centro", "Vilar dos Teles", "Coelho da Rocha", "Jardim Meriti",
+    ]),
+];
+
+enum GazetteerEntry {
+    Municipality(&'static str),
+    Neighbourhood(&'static str, &'static str),
+}
+
+lazy_static! {
+    static ref ENTRIES_BY_NAME: HashMap<String, Vec<GazetteerEntry>> = {
+        let mut entries_by_name = HashMap::<String, Vec<GazetteerEntry>>::new();
+
+        for municipality in MUNICIPALITIES {
+            entries_by_name.entry(strip_accents(municipality)).or_default().push(GazetteerEntry::Municipality(municipality));
+        }
+
+        for (municipality, neighbourhoods) in NEIGHBOURHOODS {
+            for neighbourhood in neighbourhoods.iter() {
+                entries_by_name.entry(strip_accents(neighbourhood)).or_default().push(GazetteerEntry::Neighbourhood(neighbourhood, municipality));
+            }
+        }
+
+        entries_by_name
+    };
+
+    // NOTE: Names are matched case-sensitively, so common words that are
+    // also neighbourhoods (e.g. "saúde", "lagoa") only match when written
+    // as proper nouns. Longer names come first so "Penha Circular" wins
+    // over "Penha".
+    static ref PLACES_RE: Regex = {
+        let mut names = ENTRIES_BY_NAME.keys().collect::<Vec<_>>();
+        names.sort_by_key(|name| std::cmp::Reverse(name.len()));
+
+        let alternation = names.into_iter().map(|name| regex::escape(name)).collect::<Vec<_>>().join("|");
+
+        Regex::new(&format!(r"\b(?:{})\b", alternation)).unwrap()
+    };
+}
+
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Places {
+    pub neighbourhoods: BTreeSet<&'static str>,
+    pub municipalities: BTreeSet<&'static str>,
+}
+
+impl Places {
+    // NOTE: Subscriptions may name either a neighbourhood or a whole
+    // municipality. The comparison ignores case and accents.
+    pub fn mentions_any(&self, names: &[String]) -> bool {
+        let keys = names.iter().map(|name| normalize(name)).collect::<Vec<_>>();
+
+        self.neighbourhoods
+            .iter()
+            .chain(self.municipalities.iter())
+            .any(|place| keys.contains(&normalize(place)))
+    }
+}
+
+pub fn extract_places(text: &str) -> Places {
+    let mut places = Places::default();
+
+    for found in PLACES_RE.find_iter(&strip_accents(text)) {
+        for entry in &ENTRIES_BY_NAME[found.as_str()] {
+            match entry {
+                GazetteerEntry::Municipality(municipality) => {
+                    places.municipalities.insert(municipality);
+                },
+                GazetteerEntry::Neighbourhood(neighbourhood, municipality) => {
+                    places.neighbourhoods.insert(neighbourhood);
+                    places.municipalities.insert(municipality);
+                },
+            }
+        }
+    }
+
+    places
+}
+
+pub fn is_known_place(name: &str) -> bool {
+    canonical_name(name).is_some()
+}
+
+// NOTE: Returns the name as spelled in the gazetteer, so user input like
+// "campo grande" can be stored as "Campo Grande".
+pub fn canonical_name(name: &str) -> Option<&'static str> {
+    let key = normalize(name);
+
+    ENTRIES_BY_NAME
+        .iter()
+        .find(|(known, _)| known.to_lowercase() == key)
+        .and_then(|(_, entries)| entries.first())
+        .map(|entry| match entry {
+            GazetteerEntry::Municipality(municipality) => *municipality,
+            GazetteerEntry::Neighbourhood(neighbourhood, _) => *neighbourhood,
+        })
+}
+
+pub fn normalize(name: &str) -> String {
+    strip_accents(name.trim()).to_lowercase()
+}
+
+fn strip_accents(text: &str) -> String {
+    text.chars()
+        .map(|c| match c {
+            'á' | 'à' | 'â' | 'ã' | 'ä' => 'a',
+            'Á' | 'À' | 'Â' | 'Ã' | 'Ä' => 'A',
+            'é' | 'è' | 'ê' | 'ë' => 'e',
+            'É' | 'È' | 'Ê' | 'Ë' => 'E',
+            'í' | 'ì' | 'î' | 'ï' => 'i',
+            'Í' | 'Ì' | 'Î' | 'Ï' => 'I',
+            'ó' | 'ò' | 'ô' | 'õ' | 'ö' => 'o',
+            'Ó' | 'Ò' | 'Ô' | 'Õ' | 'Ö' => 'O',
+            'ú' | 'ù' | 'û' | 'ü' => 'u',
+            'Ú' | 'Ù' | 'Û' | 'Ü' => 'U',
+            'ç' => 'c',
+            'Ç' => 'C',
+            _ => c,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{canonical_name, extract_places, is_known_place};
+
+    #[test]
+    fn extracts_neighbourhoods_and_municipalities() {
+        let places = extract_places("Manobra afeta Campo Grande, Bangu e a Penha Circular. A saúde pública agradece.");
+
+        assert_eq!(places.neighbourhoods.into_iter().collect::<Vec<_>>(), vec!["Bangu", "Campo Grande", "Penha Circular"]);
+        assert_eq!(places.municipalities.into_iter().collect::<Vec<_>>(), vec!["Rio de Janeiro"]);
+    }
+
+    #[test]
+    fn matches_names_written_without_accents() {
+        let places = extract_places("Obra em Icarai, Niteroi");
+
+        assert!(places.mentions_any(&["icaraí".to_string()]));
+        assert!(places.mentions_any(&["Niterói".to_string()]));
+        assert!(!places.mentions_any(&["Bangu".to_string()]));
+    }
+
+    #[test]
+    fn knows_places_regardless_of_case_and_accents() {
+        assert!(is_known_place("campo grande"));
+        assert!(is_known_place("NITEROI"));
+        assert!(!is_known_place("Atlântida"));
+        assert_eq!(canonical_name("sao cristovao"), Some("São Cristóvão"));
+    }
+}
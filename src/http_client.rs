@@ -0,0 +1,125 @@
+use std::{collections::HashMap, sync::{Arc, Mutex}, time::Duration};
+
+use async_trait::async_trait;
+use reqwest::{Proxy, Url};
+use tokio::{sync::{OwnedSemaphorePermit, Semaphore}, time::sleep};
+
+use crate::{error::Error, fetchers::PageFetcher};
+
+pub struct HttpClientConfig {
+    pub user_agent: String,
+    pub proxy: Option<String>,
+
+    pub connect_timeout: Duration,
+    pub read_timeout: Duration,
+
+    pub max_retries: u32,
+    pub retry_base_delay: Duration,
+
+    pub max_requests_per_host: usize,
+}
+
+impl Default for HttpClientConfig {
+    fn default() -> Self {
+        Self {
+            user_agent: concat!(env!("CARGO_PKG_NAME"), "/", env!("CARGO_PKG_VERSION")).to_string(),
+            proxy: None,
+
+            connect_timeout: Duration::from_secs(10),
+            read_timeout: Duration::from_secs(30),
+
+            max_retries: 3,
+            retry_base_delay: Duration::from_millis(500),
+
+            max_requests_per_host: 4,
+        }
+    }
+}
+
+pub struct HttpClient {
+    client: reqwest::Client,
+
+    max_retries: u32,
+    retry_base_delay: Duration,
+
+    max_requests_per_host: usize,
+    host_limits: Mutex<HashMap<String, Arc<Semaphore>>>,
+}
+
+#[async_trait(?Send)]
+impl PageFetcher for HttpClient {
+    async fn fetch(&self, url: Url) -> Result<String, Error> {
+        self.get_text(url).await
+    }
+}
+
+impl HttpClient {
+    pub fn new(config: HttpClientConfig) -> Result<Self, Error> {
+        let mut builder = reqwest::Client::builder()
+            .user_agent(config.user_agent)
+            .connect_timeout(config.connect_timeout)
+            .read_timeout(config.read_timeout);
+
+        if let Some(proxy_url) = config.proxy {
+            builder = builder.proxy(Proxy::all(proxy_url)?);
+        }
+
+        Ok(Self {
+            client: builder.build()?,
+
+            max_retries: config.max_retries,
+            retry_base_delay: config.retry_base_delay,
+
+            max_requests_per_host: config.max_requests_per_host.max(1),
+            host_limits: Mutex::new(HashMap::new()),
+        })
+    }
+
+    pub async fn get_text(&self, url: Url) -> Result<String, Error> {
+        let mut attempts = 0;
+
+        loop {
+            attempts += 1;
+
+            let result = {
+                let _permit = self.acquire_host_permit(&url).await;
+                self.try_get_text(url.clone()).await
+            };
+
+            match result {
+                Ok(text) => return Ok(text),
+                Err(error) if attempts <= self.max_retries && is_retryable(&error) => {
+                    // NOTE: Exponential backoff: base, 2 * base, 4 * base, ...
+                    sleep(self.retry_base_delay * 2u32.pow(attempts - 1)).await;
+                },
+                Err(error) => return Err(Error::RequestFailed { url, attempts, error }),
+            }
+        }
+    }
+
+    async fn try_get_text(&self, url: Url) -> Result<String, reqwest::Error> {
+        self.client.get(url).send().await?.error_for_status()?.text().await
+    }
+
+    // NOTE: Every host gets its own semaphore, so a slow provider only
+    // throttles its own requests and never the other providers'.
+    async fn acquire_host_permit(&self, url: &Url) -> OwnedSemaphorePermit {
+        let semaphore = {
+            let mut host_limits = self.host_limits.lock().expect("Host limits lock poisoned");
+            let host = url.host_str().unwrap_or_default().to_string();
+
+            host_limits
+                .entry(host)
+                .or_insert_with(|| Arc::new(Semaphore::new(self.max_requests_per_host)))
+                .clone()
+        };
+
+        semaphore.acquire_owned().await.expect("Host semaphore is never closed")
+    }
+}
+
+fn is_retryable(error: &reqwest::Error) -> bool {
+    error.is_timeout()
+        || error.is_connect()
+        || error.status().is_some_and(|status| status.is_server_error())
+}
@@ -3,57 +3,375 @@ mod news_post;
 mod telegram_bot;
 mod error;
 mod database;
+mod http_client;
+mod fetchers;
+mod config;
+mod gazetteer;
+mod bot_commands;
+mod delivery;
+mod scheduler;
+mod rate_limiter;
+mod cli;
+mod dry_run;
 
+use bot_commands::CommandHandler;
+use chrono::NaiveDate;
+use clap::Parser;
+use cli::{Cli, Command, ConfigCommand, DbCommand};
+use config::Config;
 use database::Database;
 use dotenv::dotenv;
 
 use error::Error;
-use scrapers::{aguas_do_rio_scraper::AguasDoRioScraper, cedae_scraper::CedaeScraper, igua_scraper::IguaScraper, rio_saneamento_scraper::RioSaneamentoScraper, Scraper};
-use telegram_bot::{TelegramBot, TelegramParseMode};
+use fetchers::{fixture_fetcher::FixtureFetcher, recording_fetcher::RecordingFetcher, PageFetcher};
+use http_client::HttpClient;
+use scheduler::Scheduler;
+use scrapers::{PageLimit, Scraper};
+use telegram_bot::TelegramBot;
+use tokio::sync::watch;
 
-use std::env;
+use std::{path::{Path, PathBuf}, process, sync::Arc};
+
+const SEARCH_RESULTS_COUNT: usize = 20;
 
 #[tokio::main]
 async fn main() {
     dotenv().ok();
 
-    let api_key = env::var("BOT_API_KEY").expect("Could not read BOT_API_KEY");
-    let chat_id = env::var("CHAT_ID").expect("Could not read CHAT_ID");
-    let bot_owner_chat_id = env::var("BOT_OWNER_CHAT_ID").expect("Could not read BOT_OWNER_CHAT_ID");
-    let bot = telegram_bot::TelegramBot::new(api_key).await;
+    let command = Cli::parse().into_command();
+    let config = Config::load().unwrap_or_else(|error| exit_with_error(error));
+
+    // NOTE: Only commands that talk to Telegram need its credentials, and
+    // only their errors are reported to the bot owner.
+    let result = match command {
+        Command::DryRun { output } => match http_fetcher(&config) {
+            Ok(fetcher) => dry_run(&config, fetcher, output.as_deref()).await,
+            Err(error) => Err(error),
+        },
+        // NOTE: Replays only preview what would be sent, so reproducing a
+        // failure never posts old notices to the real chats.
+        Command::Replay { directory, output } => match FixtureFetcher::new(&directory) {
+            Ok(fetcher) => dry_run(&config, Arc::new(fetcher), output.as_deref()).await,
+            Err(error) => Err(error),
+        },
+        Command::Seed => seed(&config).await,
+        Command::Backfill { provider, pages, until } => backfill(provider.as_deref(), pages, until, &config).await,
+        Command::Scrape { provider } => scrape_provider(&provider, &config).await,
+        Command::Record { directory } => record_fixtures(directory, &config).await,
+        Command::Search { terms } => search_posts(&terms.join(" "), &config),
+        Command::Db { command } => run_db_command(command, &config),
+        Command::Config { command: ConfigCommand::Check } => check_config(&config),
+        command => {
+            run_with_telegram(command, &config).await;
+            return;
+        },
+    };
+
+    if let Err(error) = result {
+        exit_with_error(error);
+    }
+}
+
+async fn run_with_telegram(command: Command, config: &Config) {
+    if let Err(error) = config.validate_telegram() {
+        exit_with_error(error);
+    }
+
+    let bot_owner_chat_id = &config.telegram.owner_chat_id;
+    let bot = telegram_bot::TelegramBot::new(config.telegram_bot_config()).await.unwrap_or_else(|error| exit_with_error(error));
 
-    match get_posts_and_send_to_telegram(&bot, &chat_id).await {
-        Ok(_) => {},
-        Err(error) => {
-            bot.send_message("*Error running bot:* _Comunicados Aguas do Rio_", &bot_owner_chat_id, TelegramParseMode::Markdown).await.expect("Error while handling error");
-            bot.send_message(&error.to_string(), &bot_owner_chat_id, TelegramParseMode::PlainText).await.expect("Error while handling error");
+    let result = match command {
+        Command::Listen => listen_for_commands(&bot, config).await,
+        Command::Daemon => match http_fetcher(config) {
+            Ok(fetcher) => run_daemon(&bot, config, fetcher).await,
+            Err(error) => Err(error),
+        },
+        Command::Resend { id } => resend_post(&bot, config, &id).await,
+        _ => match http_fetcher(config) {
+            Ok(fetcher) => get_posts_and_send_to_telegram(&bot, config, fetcher).await,
+            Err(error) => Err(error),
         },
+    };
+
+    if let Err(error) = result {
+        delivery::report_error(&bot, bot_owner_chat_id, &error).await.expect("Error while handling error");
+    }
+}
+
+fn exit_with_error(error: Error) -> ! {
+    eprintln!("{}", error);
+    process::exit(1);
+}
+
+fn http_fetcher(config: &Config) -> Result<Arc<dyn PageFetcher>, Error> {
+    Ok(Arc::new(HttpClient::new(config.http_client_config())?))
+}
+
+fn format_date(post: &news_post::NewsPost) -> String {
+    post.date().as_ref().map(|date| date.format("%d/%m/%Y").to_string()).unwrap_or_else(|| "undated".to_string())
+}
+
+// NOTE: Works on a snapshot of the database and never talks to Telegram, so
+// it can run against the production config.
+async fn dry_run(config: &Config, fetcher: Arc<dyn PageFetcher>, output: Option<&Path>) -> Result<(), Error> {
+    let database = sync_config(Database::snapshot(&config.database.path)?, config)?;
+    let (posts, failures) = scrapers::scrape_all(fetcher, &config.scrapers).await;
+
+    dry_run::run(&database, posts, &config.delivery, output)?;
+
+    if !failures.is_empty() {
+        return Err(Error::ScrapersFailed(failures));
     }
+
+    Ok(())
 }
 
-async fn get_posts_and_send_to_telegram(bot: &TelegramBot, chat_id: &str) -> Result<(), Error> {
-    let database = Database::new()?;
+async fn seed(config: &Config) -> Result<(), Error> {
+    let database = open_database(config)?;
+    let (posts, failures) = scrapers::scrape_all(http_fetcher(config)?, &config.scrapers).await;
+
+    let count = posts.len();
+    delivery::seed_posts(&database, posts)?;
+
+    println!("Marked {} post(s) as delivered", count);
 
-    let scrapers: Vec<Box<dyn Scraper>> = vec![
-        Box::new(CedaeScraper::new()), 
-        Box::new(RioSaneamentoScraper::new()), 
-        Box::new(IguaScraper::new()),
-        Box::new(AguasDoRioScraper::new()),
-    ];
+    if !failures.is_empty() {
+        return Err(Error::ScrapersFailed(failures));
+    }
+
+    Ok(())
+}
 
+// NOTE: Backfilled posts are stored as if they had been delivered, so they
+// can be searched but are never sent. The pages read on every run are
+// skipped, so posts published since the last run are still delivered.
+async fn backfill(provider: Option<&str>, pages: usize, until: Option<NaiveDate>, config: &Config) -> Result<(), Error> {
+    let database = open_database(config)?;
+    let scrapers = scrapers::build_scrapers(http_fetcher(config)?, &config.scrapers);
+
+    let scrapers = match provider {
+        Some(provider) => vec![find_scraper(&scrapers, provider)?],
+        None => scrapers.iter().map(Box::as_ref).collect(),
+    };
+
+    let mut failures = vec![];
     for scraper in scrapers {
-        let posts = scraper.get_posts().await?;
+        let limit = PageLimit { first_page: config.scrapers.pages_for(scraper.name()).unwrap_or(1), max_pages: pages, until };
+
+        match scrapers::get_pages(scraper, &limit).await {
+            Ok(posts) => {
+                let read = posts.len();
+                let stored = delivery::backfill_posts(&database, posts)?;
+                println!("Read {} post(s) from {}, {} of them new", read, scraper.name(), stored);
+            },
+            Err(error) => failures.push((scraper.name().to_string(), error)),
+        }
+    }
+
+    if !failures.is_empty() {
+        return Err(Error::ScrapersFailed(failures));
+    }
+
+    Ok(())
+}
+
+async fn scrape_provider(provider: &str, config: &Config) -> Result<(), Error> {
+    let scrapers = scrapers::build_scrapers(http_fetcher(config)?, &config.scrapers);
+    let posts = find_scraper(&scrapers, provider)?.get_posts().await?;
+
+    println!("{}", serde_json::to_string_pretty(&posts)?);
 
-        for post in posts {
-            if database.post_exists(post.id())? {
-                continue;
+    Ok(())
+}
+
+// NOTE: Provider names are matched ignoring case and accents, so "igua"
+// finds "Iguá".
+fn find_scraper<'a>(scrapers: &'a [Box<dyn Scraper>], provider: &str) -> Result<&'a dyn Scraper, Error> {
+    scrapers
+        .iter()
+        .find(|scraper| gazetteer::normalize(scraper.name()) == gazetteer::normalize(provider))
+        .map(Box::as_ref)
+        .ok_or_else(|| Error::UnknownProvider(provider.to_string()))
+}
+
+fn run_db_command(command: DbCommand, config: &Config) -> Result<(), Error> {
+    let database = Database::new(&config.database.path)?;
+
+    match command {
+        DbCommand::List { limit } => {
+            for post in database.latest_posts(limit)? {
+                println!("{} {} [{}] {}", post.id(), format_date(&post), post.provider(), post.title());
             }
+        },
+        DbCommand::Show { id } => {
+            let post = database.post(&id)?.ok_or_else(|| Error::PostNotFound(id.clone()))?;
 
-            bot.send_message(&post.as_markdown_string(), chat_id, TelegramParseMode::Markdown).await?;
+            println!("Id: {}", post.id());
+            println!("Revision: {}", post.revision());
+            println!("Provider: {}", post.provider());
+            println!("Title: {}", post.title());
+            println!("URL: {}", post.url());
+            println!("Date: {}", post.date().map(|date| date.to_rfc3339()).unwrap_or_else(|| "undated".to_string()));
 
-            database.save_post(post.id(), post.date())?;
-        }
+            let deliveries = database.deliveries_for_post(&id)?;
+            if deliveries.is_empty() {
+                println!("Delivered to: nobody");
+            }
+            for (chat_id, delivered_at) in deliveries {
+                println!("Delivered to: chat {} at {}", chat_id, delivered_at);
+            }
+
+            for entry in database.unsent_outbox_entries_for_post(&id)? {
+                match entry.last_error {
+                    Some(last_error) => println!("Not sent: {} to chat {} is {}, last error: {}", entry.kind.as_str(), entry.chat_id, entry.state, last_error.trim_end()),
+                    None => println!("Not sent: {} to chat {} is {}", entry.kind.as_str(), entry.chat_id, entry.state),
+                }
+            }
+
+            println!("\n{}", post.content());
+        },
+        DbCommand::Forget { id } => {
+            if !database.forget_post(&id)? {
+                return Err(Error::PostNotFound(id));
+            }
+
+            println!("Forgot post {}. It will be delivered as new if it is still listed on the next run.", id);
+        },
     }
 
     Ok(())
-}
\ No newline at end of file
+}
+
+fn check_config(config: &Config) -> Result<(), Error> {
+    config.validate_telegram()?;
+
+    println!("Database: {}", config.database.path.display());
+
+    for (provider, enabled) in config.scrapers.providers() {
+        println!("Provider {}: {}", provider, if enabled { "enabled" } else { "disabled" });
+    }
+
+    for (provider, chat_id) in config.routes() {
+        println!("Route: {} -> chat {}", provider, chat_id);
+    }
+
+    for (chat_id, neighbourhood) in config.neighbourhood_filters() {
+        println!("Filter: chat {} only gets posts mentioning {}", chat_id, neighbourhood);
+    }
+
+    println!("The configuration is valid");
+
+    Ok(())
+}
+
+async fn resend_post(bot: &TelegramBot, config: &Config, id: &str) -> Result<(), Error> {
+    let database = open_database(config)?;
+    let post = database.post(id)?.ok_or_else(|| Error::PostNotFound(id.to_string()))?;
+
+    delivery::resend_post(bot, &database, &post, &config.delivery).await
+}
+
+async fn record_fixtures(directory: PathBuf, config: &Config) -> Result<(), Error> {
+    let http_client = Arc::new(HttpClient::new(config.http_client_config())?);
+    let fetcher = Arc::new(RecordingFetcher::new(http_client, &directory)?);
+
+    let (posts, failures) = scrapers::scrape_all(fetcher.clone(), &config.scrapers).await;
+
+    println!("Recorded {} page(s) with {} post(s) to {}", fetcher.recorded_pages(), posts.len(), directory.display());
+
+    if !failures.is_empty() {
+        return Err(Error::ScrapersFailed(failures));
+    }
+
+    Ok(())
+}
+
+fn search_posts(query: &str, config: &Config) -> Result<(), Error> {
+    let database = Database::new(&config.database.path)?;
+    let posts = database.search_posts(query, SEARCH_RESULTS_COUNT)?;
+
+    if posts.is_empty() {
+        println!("No posts found for \"{}\"", query);
+    }
+
+    for post in posts {
+        println!("{} [{}] {}\n  {}", format_date(&post), post.provider(), post.title(), post.url());
+    }
+
+    Ok(())
+}
+
+fn open_database(config: &Config) -> Result<Database, Error> {
+    sync_config(Database::new(&config.database.path)?, config)
+}
+
+fn sync_config(database: Database, config: &Config) -> Result<Database, Error> {
+    if !config.telegram.chat_id.trim().is_empty() {
+        database.adopt_legacy_deliveries(&config.telegram.chat_id)?;
+    }
+    database.sync_config_routes(&config.routes())?;
+    database.sync_config_neighbourhoods(&config.neighbourhood_filters())?;
+
+    Ok(database)
+}
+
+async fn listen_for_commands(bot: &TelegramBot, config: &Config) -> Result<(), Error> {
+    let database = open_database(config)?;
+
+    CommandHandler::new(bot, &database, config).run_update_loop().await;
+
+    Ok(())
+}
+
+// NOTE: Scrapes on a schedule and answers commands at the same time, until
+// SIGINT or SIGTERM is received.
+async fn run_daemon(bot: &TelegramBot, config: &Config, fetcher: Arc<dyn PageFetcher>) -> Result<(), Error> {
+    let database = open_database(config)?;
+
+    let scheduler = Scheduler::new(bot, &database, config, scrapers::build_scrapers(fetcher, &config.scrapers));
+    let command_handler = CommandHandler::new(bot, &database, config);
+
+    let (shutdown_sender, shutdown_receiver) = watch::channel(false);
+
+    let shutdown = async {
+        scheduler::wait_for_shutdown_signal().await;
+        println!("Shutting down after the current cycle");
+
+        let _ = shutdown_sender.send(true);
+    };
+
+    let work = async {
+        tokio::select! {
+            _ = scheduler.run(shutdown_receiver) => {},
+            _ = command_handler.run_update_loop() => {},
+        }
+    };
+
+    tokio::join!(shutdown, work);
+
+    Ok(())
+}
+
+async fn get_posts_and_send_to_telegram(bot: &TelegramBot, config: &Config, fetcher: Arc<dyn PageFetcher>) -> Result<(), Error> {
+    let database = open_database(config)?;
+
+    let (posts, failures) = scrapers::scrape_all(fetcher, &config.scrapers).await;
+
+    // NOTE: Failed deliveries must not hide failed scrapers, so both are
+    // reported together.
+    let mut errors = vec![];
+
+    if let Err(error) = delivery::deliver_posts(bot, &database, posts, &config.delivery).await {
+        errors.push(error);
+    }
+
+    if !failures.is_empty() {
+        errors.push(Error::ScrapersFailed(failures));
+    }
+
+    match errors.len() {
+        0 => Ok(()),
+        1 => Err(errors.remove(0)),
+        _ => Err(Error::RunFailed(errors)),
+    }
+}
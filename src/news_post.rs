@@ -1,33 +1,58 @@
 use std::{borrow::Cow, fmt::Write};
 
-use chrono::NaiveDate;
+use chrono::{DateTime, NaiveDate, NaiveDateTime, NaiveTime, TimeDelta, TimeZone};
+use chrono_tz::Tz;
 use lazy_static::lazy_static;
 use regex::Regex;
+use reqwest::Url;
+use serde::Serialize;
 use sha1::{Digest, Sha1};
+use similar::{ChangeTag, TextDiff};
+
+use crate::{gazetteer::{self, Places}, telegram_bot::escape_html};
+
+// NOTE: Every provider publishes in Rio de Janeiro's local time.
+pub const TIMEZONE: Tz = chrono_tz::America::Sao_Paulo;
 
 lazy_static! {
     static ref LINE_BREAK_RE: Regex = Regex::new(r"(\r?\n)+").unwrap();
 }
 
-#[derive(Debug, Clone)]
+// NOTE: Providers that only publish the day of a post have their dates
+// placed at the start of that day, which must not be shown as a time.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PostDate {
+    pub date_time: DateTime<Tz>,
+    pub has_time: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
 pub struct NewsPost {
+    // NOTE: The id identifies the post across edits, while the revision
+    // changes whenever its content does.
     id: String,
-    
+    revision: String,
+
+    provider: String,
     title: String,
     url: String,
     content: String,
-    date: Option<NaiveDate>,
+    date: Option<DateTime<Tz>>,
+    date_has_time: bool,
 }
 
 impl NewsPost {
-    pub fn new(title: String, url: String, content: String, date: Option<NaiveDate>) -> Self {
+    pub fn new(provider: &str, title: String, url: String, content: String, date: Option<PostDate>) -> Self {
         Self {
-            id: sha1_digest(&content),
+            id: sha1_digest(&format!("{}\n{}", provider, canonical_url(&url))),
+            revision: sha1_digest(&content),
 
+            provider: provider.to_string(),
             title,
             url,
             content,
-            date
+            date: date.map(|date| date.date_time),
+            date_has_time: date.is_some_and(|date| date.has_time),
         }
     }
 
@@ -35,22 +60,84 @@ impl NewsPost {
         &self.id
     }
 
-    pub fn date(&self) -> &Option<NaiveDate>  {
+    pub fn revision(&self) -> &str {
+        &self.revision
+    }
+
+    pub fn provider(&self) -> &str {
+        &self.provider
+    }
+
+    pub fn title(&self) -> &str {
+        &self.title
+    }
+
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    pub fn content(&self) -> &str {
+        &self.content
+    }
+
+    pub fn date(&self) -> &Option<DateTime<Tz>>  {
         &self.date
     }
 
-    pub fn as_markdown_string(&self) -> String {
-        let date_str = self.date.map(|d| d.format("%d/%m/%Y").to_string()).unwrap_or("-".to_string());
+    pub fn date_has_time(&self) -> bool {
+        self.date_has_time
+    }
+
+    pub fn places(&self) -> Places {
+        gazetteer::extract_places(&format!("{}\n{}", self.title, self.content))
+    }
+
+    // NOTE: Titles and contents are scraped text, so everything rendered for
+    // Telegram's HTML parse mode is escaped.
+    pub fn as_html_link(&self) -> String {
+        format!("<a href=\"{}\">{}</a>", escape_html(&self.url), escape_html(&self.title))
+    }
+
+    pub fn as_html_string(&self) -> String {
+        let date_str = self.date.map(|date| format_date(date, self.date_has_time)).unwrap_or("-".to_string());
 
         let mut ans = String::new();
-        write!(&mut ans, "[{}]({})\n\n", self.title, self.url).expect("Unexpected error formating post");
-        write!(&mut ans, "_Data: {}_\n\n", date_str).expect("Unexpected error formating post");
+        write!(&mut ans, "{}\n\n", self.as_html_link()).expect("Unexpected error formating post");
+        write!(&mut ans, "<i>Data: {}</i>\n\n", date_str).expect("Unexpected error formating post");
 
-        ans.push_str(self.formated_content().as_ref());
+        ans.push_str(&escape_html(self.formated_content().as_ref()));
         
         ans
     }
 
+    // NOTE: Returns None when the edit does not change any visible text,
+    // e.g. when only whitespace was touched.
+    pub fn as_update_html_string(&self, previous_content: &str) -> Option<String> {
+        let previous_lines = LINE_BREAK_RE.replace_all(previous_content.trim(), "\n");
+        let current_lines = LINE_BREAK_RE.replace_all(self.content.trim(), "\n");
+
+        let mut changes = String::new();
+        for change in TextDiff::from_lines(previous_lines.as_ref(), current_lines.as_ref()).iter_all_changes() {
+            let marker = match change.tag() {
+                ChangeTag::Delete => "➖",
+                ChangeTag::Insert => "➕",
+                ChangeTag::Equal => continue,
+            };
+
+            write!(&mut changes, "{} {}\n\n", marker, escape_html(change.value().trim_end())).expect("Unexpected error formating post");
+        }
+
+        if changes.is_empty() {
+            return None;
+        }
+
+        let mut ans = String::new();
+        write!(&mut ans, "<b>Atualização:</b> {}\n\n", self.as_html_link()).expect("Unexpected error formating post");
+        ans.push_str(changes.trim_end());
+
+        Some(ans)
+    }
+
     fn formated_content(&self) -> Cow<'_, str> {
         let trimmed_content = self.content.trim();
         let ans = LINE_BREAK_RE.replace_all(trimmed_content, "\n\n");
@@ -59,6 +146,62 @@ impl NewsPost {
     }
 }
 
+// NOTE: Providers sometimes link the same post with tracking parameters, a
+// fragment or a trailing slash, which must not change its identity.
+fn canonical_url(url: &str) -> String {
+    let Ok(mut url) = Url::parse(url) else {
+        return url.trim().to_string();
+    };
+
+    url.set_fragment(None);
+
+    let query_pairs = url.query_pairs()
+        .filter(|(key, _)| !key.starts_with("utm_"))
+        .map(|(key, value)| (key.into_owned(), value.into_owned()))
+        .collect::<Vec<_>>();
+
+    if query_pairs.is_empty() {
+        url.set_query(None);
+    } else {
+        url.query_pairs_mut().clear().extend_pairs(query_pairs);
+    }
+
+    if url.path().len() > 1 && url.path().ends_with('/') {
+        let path = url.path().trim_end_matches('/').to_string();
+        url.set_path(&path);
+    }
+
+    url.to_string()
+}
+
+// NOTE: Posts whose provider only publishes a date are placed at the start
+// of that day.
+pub fn local_date(date: NaiveDate) -> PostDate {
+    PostDate { date_time: to_local(date.and_time(NaiveTime::MIN)), has_time: false }
+}
+
+pub fn local_date_time(date_time: NaiveDateTime) -> PostDate {
+    PostDate { date_time: to_local(date_time), has_time: true }
+}
+
+// NOTE: Daylight saving time used to start at midnight in Brazil, so times
+// skipped by it are moved one hour ahead.
+fn to_local(date_time: NaiveDateTime) -> DateTime<Tz> {
+    TIMEZONE
+        .from_local_datetime(&date_time)
+        .earliest()
+        .or_else(|| TIMEZONE.from_local_datetime(&(date_time + TimeDelta::hours(1))).earliest())
+        .expect("Unexpected gap in local time")
+}
+
+fn format_date(date: DateTime<Tz>, has_time: bool) -> String {
+    if has_time {
+        date.format("%d/%m/%Y %H:%M").to_string()
+    } else {
+        date.format("%d/%m/%Y").to_string()
+    }
+}
+
 fn sha1_digest(msg: &String) -> String {
     let mut hasher = Sha1::new();
     hasher.update(msg);
@@ -74,4 +217,65 @@ fn encode_to_hex(data: &[u8]) -> String {
     }
 
     ans
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{NaiveDate, NaiveTime};
+
+    use super::{local_date, local_date_time, NewsPost};
+
+    #[test]
+    fn keeps_the_id_when_the_content_changes() {
+        let original = NewsPost::new("CEDAE", "Obra".to_string(), "https://cedae.com.br/noticia/obra/".to_string(), "Previsão: 18h.".to_string(), None);
+        let edited = NewsPost::new("CEDAE", "Obra".to_string(), "https://cedae.com.br/noticia/obra?utm_source=x#topo".to_string(), "Previsão: 22h.".to_string(), None);
+        let other_provider = NewsPost::new("Iguá", "Obra".to_string(), "https://cedae.com.br/noticia/obra/".to_string(), "Previsão: 18h.".to_string(), None);
+
+        assert_eq!(original.id(), edited.id());
+        assert_ne!(original.revision(), edited.revision());
+        assert_ne!(original.id(), other_provider.id());
+    }
+
+    #[test]
+    fn escapes_scraped_text() {
+        let post = NewsPost::new("CEDAE", "Obra <urgente> & reparo_rede".to_string(), "https://cedae.com.br/obra?a=1&b=2".to_string(), "Trecho *entre* [ruas] `A` & B.".to_string(), None);
+
+        assert_eq!(
+            post.as_html_string(),
+            "<a href=\"https://cedae.com.br/obra?a=1&amp;b=2\">Obra &lt;urgente&gt; &amp; reparo_rede</a>\n\n<i>Data: -</i>\n\nTrecho *entre* [ruas] `A` &amp; B.",
+        );
+    }
+
+    #[test]
+    fn shows_the_time_only_when_known() {
+        let dated = NewsPost::new("CEDAE", "Obra".to_string(), "https://cedae.com.br/obra".to_string(), String::new(), NaiveDate::from_ymd_opt(2024, 10, 14).map(local_date));
+        let timed = NewsPost::new("CEDAE", "Obra".to_string(), "https://cedae.com.br/obra".to_string(), String::new(), NaiveDate::from_ymd_opt(2024, 10, 14).map(|date| local_date_time(date.and_hms_opt(15, 30, 0).unwrap())));
+
+        let midnight = NewsPost::new("CEDAE", "Obra".to_string(), "https://cedae.com.br/obra".to_string(), String::new(), NaiveDate::from_ymd_opt(2024, 10, 14).map(|date| local_date_time(date.and_time(NaiveTime::MIN))));
+
+        assert!(dated.as_html_string().contains("<i>Data: 14/10/2024</i>"));
+        assert!(timed.as_html_string().contains("<i>Data: 14/10/2024 15:30</i>"));
+        assert!(midnight.as_html_string().contains("<i>Data: 14/10/2024 00:00</i>"));
+        assert_eq!(timed.date().unwrap().to_rfc3339(), "2024-10-14T15:30:00-03:00");
+    }
+
+    #[test]
+    fn handles_midnights_skipped_by_daylight_saving_time() {
+        let date = local_date(NaiveDate::from_ymd_opt(2018, 11, 4).unwrap());
+        let post = NewsPost::new("CEDAE", "Obra".to_string(), "https://cedae.com.br/obra".to_string(), String::new(), Some(date));
+
+        assert_eq!(date.date_time.time(), NaiveTime::from_hms_opt(1, 0, 0).unwrap());
+        assert!(post.as_html_string().contains("<i>Data: 04/11/2018</i>"));
+    }
+
+    #[test]
+    fn formats_updates_as_a_diff() {
+        let post = NewsPost::new("CEDAE", "Obra".to_string(), "https://cedae.com.br/obra".to_string(), "Obra em Bangu.\nPrevisão: 22h.".to_string(), None);
+
+        assert_eq!(
+            post.as_update_html_string("Obra em Bangu.\n\nPrevisão: 18h.").unwrap(),
+            "<b>Atualização:</b> <a href=\"https://cedae.com.br/obra\">Obra</a>\n\n➖ Previsão: 18h.\n\n➕ Previsão: 22h.",
+        );
+        assert_eq!(post.as_update_html_string("  Obra em Bangu.\r\nPrevisão: 22h.\n"), None);
+    }
+}
@@ -0,0 +1,72 @@
+use std::{collections::HashMap, sync::Mutex, time::Duration};
+
+use tokio::time::{sleep_until, Instant};
+
+// NOTE: Telegram allows about one message per second in a chat (twenty per
+// minute in groups) and thirty per second overall. Every message reserves
+// the earliest slot allowed by both limits and waits for it, so concurrent
+// senders never exceed them.
+pub struct RateLimiter {
+    per_chat_interval: Duration,
+    global_interval: Duration,
+
+    next_slots: Mutex<NextSlots>,
+}
+
+#[derive(Default)]
+struct NextSlots {
+    global: Option<Instant>,
+    chats: HashMap<String, Instant>,
+}
+
+impl RateLimiter {
+    pub fn new(per_chat_interval: Duration, global_interval: Duration) -> Self {
+        Self {
+            per_chat_interval,
+            global_interval,
+
+            next_slots: Mutex::new(NextSlots::default()),
+        }
+    }
+
+    pub async fn wait(&self, chat_id: &str) {
+        let slot = self.reserve(chat_id, Instant::now());
+
+        sleep_until(slot).await;
+    }
+
+    fn reserve(&self, chat_id: &str, now: Instant) -> Instant {
+        let mut next_slots = self.next_slots.lock().expect("Rate limiter lock poisoned");
+
+        let slot = [Some(now), next_slots.global, next_slots.chats.get(chat_id).copied()]
+            .into_iter()
+            .flatten()
+            .max()
+            .unwrap_or(now);
+
+        next_slots.global = Some(slot + self.global_interval);
+        next_slots.chats.insert(chat_id.to_string(), slot + self.per_chat_interval);
+
+        slot
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use tokio::time::Instant;
+
+    use super::RateLimiter;
+
+    #[test]
+    fn spaces_messages_per_chat_and_globally() {
+        let rate_limiter = RateLimiter::new(Duration::from_secs(3), Duration::from_millis(100));
+        let now = Instant::now();
+
+        assert_eq!(rate_limiter.reserve("-1", now), now);
+        assert_eq!(rate_limiter.reserve("-2", now), now + Duration::from_millis(100));
+        assert_eq!(rate_limiter.reserve("-1", now), now + Duration::from_secs(3));
+        assert_eq!(rate_limiter.reserve("-3", now + Duration::from_secs(10)), now + Duration::from_secs(10));
+    }
+}
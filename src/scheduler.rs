@@ -0,0 +1,108 @@
+use std::time::Duration;
+
+use rand::Rng;
+use tokio::{sync::watch, time::{sleep_until, Instant}};
+
+use crate::{config::Config, database::Database, delivery, error::Error, scrapers::{self, Scraper}, telegram_bot::TelegramBot};
+
+// NOTE: Runs every scraper on its own interval, keeping the database and the
+// bot session open between cycles. A cycle always runs to completion: the
+// shutdown signal is only checked while waiting for the next one, so a post
+// is never left sent but not saved.
+pub struct Scheduler<'a> {
+    bot: &'a TelegramBot,
+    database: &'a Database,
+    config: &'a Config,
+    scrapers: Vec<Box<dyn Scraper>>,
+}
+
+impl<'a> Scheduler<'a> {
+    pub fn new(bot: &'a TelegramBot, database: &'a Database, config: &'a Config, scrapers: Vec<Box<dyn Scraper>>) -> Self {
+        Self {
+            bot,
+            database,
+            config,
+            scrapers,
+        }
+    }
+
+    pub async fn run(&self, mut shutdown: watch::Receiver<bool>) {
+        let mut next_runs = vec![Instant::now(); self.scrapers.len()];
+
+        while !*shutdown.borrow() {
+            let now = Instant::now();
+            let due_scrapers = self.scrapers
+                .iter()
+                .zip(next_runs.iter_mut())
+                .filter(|(_, next_run)| **next_run <= now)
+                .map(|(scraper, next_run)| {
+                    *next_run = now + self.interval_for(scraper.name());
+                    scraper.as_ref()
+                })
+                .collect::<Vec<_>>();
+
+            if !due_scrapers.is_empty() {
+                self.run_cycle(&due_scrapers).await;
+            }
+
+            let Some(next_run) = next_runs.iter().min().copied() else {
+                // NOTE: Nothing is scheduled when every scraper is disabled.
+                let _ = shutdown.changed().await;
+                break;
+            };
+
+            tokio::select! {
+                _ = sleep_until(next_run) => {},
+                _ = shutdown.changed() => {},
+            }
+        }
+    }
+
+    async fn run_cycle(&self, scrapers: &[&dyn Scraper]) {
+        let (posts, failures) = scrapers::scrape(scrapers).await;
+
+        if let Err(error) = delivery::deliver_posts(self.bot, self.database, posts, &self.config.delivery).await {
+            self.report_error(error).await;
+        }
+
+        if !failures.is_empty() {
+            self.report_error(Error::ScrapersFailed(failures)).await;
+        }
+    }
+
+    async fn report_error(&self, error: Error) {
+        eprintln!("{}", error);
+
+        if let Err(report_error) = delivery::report_error(self.bot, &self.config.telegram.owner_chat_id, &error).await {
+            eprintln!("Could not report error to the bot owner: {}", report_error);
+        }
+    }
+
+    fn interval_for(&self, provider: &str) -> Duration {
+        let interval_secs = self.config.scrapers.interval_secs_for(provider).unwrap_or(self.config.daemon.default_interval_secs);
+        let jitter_secs = match self.config.daemon.jitter_secs {
+            0 => 0,
+            jitter_secs => rand::thread_rng().gen_range(0..=jitter_secs),
+        };
+
+        Duration::from_secs(interval_secs + jitter_secs)
+    }
+}
+
+pub async fn wait_for_shutdown_signal() {
+    #[cfg(unix)]
+    {
+        let mut terminate = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+            .expect("Could not listen for SIGTERM");
+
+        tokio::select! {
+            _ = tokio::signal::ctrl_c() => {},
+            _ = terminate.recv() => {},
+        }
+    }
+
+    #[cfg(not(unix))]
+    {
+        let _ = tokio::signal::ctrl_c().await;
+    }
+}
@@ -1,12 +1,18 @@
+use std::sync::Arc;
+
 use async_trait::async_trait;
 use chrono::NaiveDate;
+use futures::future::try_join_all;
 use reqwest::Url;
 use scraper::{Html, Selector};
 use serde::Deserialize;
 
-use crate::{error::Error, news_post::NewsPost};
+use crate::{error::Error, fetchers::PageFetcher, news_post::{local_date, NewsPost}};
+
+use super::{with_query_param, Scraper};
 
-use super::Scraper;
+pub const NAME: &str = "Águas do Rio";
+pub const DEFAULT_URL: &str = "https://aguasdorio.com.br/wp-admin/admin-ajax.php?id=lista-noticias&posts_per_page=10&page=0&offset=0&repeater=default&preloaded=false&preloaded_amount=0&category=comunicados&order=DESC&orderby=date&action=alm_get_posts";
 
 #[derive(Deserialize)]
 struct ApiResponse {
@@ -14,6 +20,7 @@ struct ApiResponse {
 }
 
 pub struct AguasDoRioScraper {
+    fetcher: Arc<dyn PageFetcher>,
     base_url: Url,
 
     posts_selector: Selector,
@@ -27,11 +34,23 @@ pub struct AguasDoRioScraper {
 
 #[async_trait(?Send)]
 impl Scraper for AguasDoRioScraper {
-    async fn get_posts(&self) -> Result<Vec<NewsPost>, Error> {
-        let api_reponse = reqwest::get(self.base_url.clone()).await?.json::<ApiResponse>().await?;
+    fn name(&self) -> &str {
+        NAME
+    }
+
+    // NOTE: The list is loaded by the site's "load more" button, which asks
+    // for pages numbered from 0. Pages past the last one have no posts.
+    async fn get_page(&self, page: usize) -> Result<Vec<NewsPost>, Error> {
+        let url = match page {
+            0 => self.base_url.clone(),
+            _ => with_query_param(&self.base_url, "page", &page.to_string()),
+        };
+
+        let data = self.fetcher.fetch(url).await?;
+        let api_reponse = serde_json::from_str::<ApiResponse>(&data)?;
         let html = Html::parse_fragment(&api_reponse.html);
 
-        let mut ans = vec![];
+        let mut posts_futures = vec![];
         for post_element in html.select(&self.posts_selector) {
             let title_element = post_element.select(&self.title_selector).next().ok_or(Error::ElementNotFound(".card-title"))?;
             let date_element = post_element.select(&self.date_selector).next().ok_or(Error::ElementNotFound(".date"))?;
@@ -40,28 +59,31 @@ impl Scraper for AguasDoRioScraper {
 
             let title = title_element.text().map(str::trim).collect();
             let date_text = date_element.text().collect::<String>();
-            let mut content = content_element.text().collect::<String>();
+            let content = content_element.text().collect::<String>();
 
             let link_str = link_element.value().attr("href").ok_or(Error::AttrNotFound("href"))?;
             let url = self.base_url.join(link_str).unwrap();
 
             let date = NaiveDate::parse_from_str(date_text.trim(), "%d/%m/%Y").ok();
 
-            if content.ends_with("...") {
-                content = self.get_full_content(url.clone()).await?;
-            }
+            posts_futures.push(async move {
+                let content = if content.ends_with("...") {
+                    self.get_full_content(url.clone()).await?
+                } else {
+                    content
+                };
 
-            ans.push(NewsPost::new(title, url.to_string(), content, date));
+                Ok::<_, Error>(NewsPost::new(NAME, title, url.to_string(), content, date.map(local_date)))
+            });
         }
 
-
-        Ok(ans)
+        try_join_all(posts_futures).await
     }
 }
 
 impl AguasDoRioScraper {
     async fn get_full_content(&self, url: Url) -> Result<String, Error> {
-        let data = reqwest::get(url).await?.text().await?;
+        let data = self.fetcher.fetch(url).await?;
         let html = Html::parse_document(&data);
 
         let content_element = html.select(&self.full_content_selector).next().ok_or(Error::ElementNotFound(".article-inline-text"))?;
@@ -71,9 +93,10 @@ impl AguasDoRioScraper {
         Ok(content)
     }
 
-    pub fn new() -> Self {
+    pub fn new(fetcher: Arc<dyn PageFetcher>, base_url: Url) -> Self {
         Self {
-            base_url: Url::parse("https://aguasdorio.com.br/wp-admin/admin-ajax.php?id=lista-noticias&posts_per_page=10&page=0&offset=0&repeater=default&preloaded=false&preloaded_amount=0&category=comunicados&order=DESC&orderby=date&action=alm_get_posts").unwrap(),
+            fetcher,
+            base_url,
 
             posts_selector: Selector::parse(".content-holder").unwrap(),
             title_selector: Selector::parse(".card-title").unwrap(),
@@ -84,4 +107,47 @@ impl AguasDoRioScraper {
             full_content_selector: Selector::parse(".article-inline-text").unwrap(),
         }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::NaiveDate;
+    use reqwest::Url;
+
+    use crate::{news_post::{local_date, NewsPost}, scrapers::{fixture_fetcher, get_pages, PageLimit, Scraper}};
+
+    use super::{AguasDoRioScraper, DEFAULT_URL, NAME};
+
+    #[tokio::test]
+    async fn extracts_posts_from_fixtures() {
+        let scraper = AguasDoRioScraper::new(fixture_fetcher("aguas_do_rio"), Url::parse(DEFAULT_URL).unwrap());
+
+        let posts = scraper.get_posts().await.unwrap();
+
+        assert_eq!(posts, vec![
+            NewsPost::new(
+                NAME,
+                "Manobra na rede em Campo Grande".to_string(),
+                "https://aguasdorio.com.br/comunicados/manobra-na-rede-em-campo-grande/".to_string(),
+                "A Águas do Rio realiza manobra na rede de distribuição em Campo Grande nesta terça-feira.".to_string(),
+                NaiveDate::from_ymd_opt(2024, 10, 15).map(local_date),
+            ),
+            NewsPost::new(
+                NAME,
+                "Abastecimento em Bangu".to_string(),
+                "https://aguasdorio.com.br/comunicados/abastecimento-em-bangu/".to_string(),
+                "O abastecimento em Bangu foi normalizado.".to_string(),
+                NaiveDate::from_ymd_opt(2024, 10, 14).map(local_date),
+            ),
+        ]);
+    }
+
+    #[tokio::test]
+    async fn stops_at_the_first_empty_page() {
+        let scraper = AguasDoRioScraper::new(fixture_fetcher("aguas_do_rio"), Url::parse(DEFAULT_URL).unwrap());
+
+        let posts = get_pages(&scraper, &PageLimit { first_page: 0, max_pages: 5, until: None }).await.unwrap();
+
+        assert_eq!(posts, scraper.get_posts().await.unwrap());
+    }
+}
@@ -1,13 +1,20 @@
+use std::sync::Arc;
+
 use async_trait::async_trait;
 use chrono::NaiveDate;
+use futures::future::try_join_all;
 use reqwest::Url;
 use scraper::{selectable::Selectable, Html, Selector};
 
-use crate::{error::Error, news_post::NewsPost};
+use crate::{error::Error, fetchers::PageFetcher, news_post::{local_date, NewsPost}};
 
 use super::Scraper;
 
+pub const NAME: &str = "CEDAE";
+pub const DEFAULT_URL: &str = "https://cedae.com.br/Noticias/";
+
 pub struct CedaeScraper {
+    fetcher: Arc<dyn PageFetcher>,
     base_url: Url,
 
     news_list_selector: Selector,
@@ -18,29 +25,38 @@ pub struct CedaeScraper {
 
 #[async_trait(?Send)]
 impl Scraper for CedaeScraper {
-    async fn get_posts(&self) -> Result<Vec<NewsPost>, Error> {
-        let data = reqwest::get(self.base_url.clone()).await?.text().await?;
+    fn name(&self) -> &str {
+        NAME
+    }
+
+    // NOTE: How CEDAE pages its news list is unknown, so only the first page
+    // is read and Config::validate rejects more pages.
+    async fn get_page(&self, page: usize) -> Result<Vec<NewsPost>, Error> {
+        if page > 0 {
+            return Ok(vec![]);
+        }
+
+        let data = self.fetcher.fetch(self.base_url.clone()).await?;
         let html = Html::parse_document(&data);
 
         let news_posts_wrapper_element = html.select(&self.news_list_selector).next().ok_or(Error::ElementNotFound(".lista-busca"))?;
 
-        let mut ans = Vec::new();
+        let mut posts_futures = Vec::new();
         for news_post_element in news_posts_wrapper_element.select(&self.links_selector) {
             let post_title = news_post_element.text().map(str::trim).collect();
             let post_url = news_post_element.value().attr("href").ok_or(Error::AttrNotFound("href"))?;
 
-            let post = self.get_post_date_and_content(post_title, post_url).await?;
-            ans.push(post);
+            posts_futures.push(self.get_post_date_and_content(post_title, post_url));
         }
 
-        Ok(ans)
+        try_join_all(posts_futures).await
     }
 }
 
 impl CedaeScraper {
     async fn get_post_date_and_content(&self, title: String, post_url: &str) -> Result<NewsPost, Error> {
         let url = self.base_url.join(post_url).unwrap();
-        let post_data = reqwest::get(url.clone()).await?.text().await?;
+        let post_data = self.fetcher.fetch(url.clone()).await?;
 
         let html = Html::parse_document(&post_data);
         let date_element = html.select(&self.date_element_selector).next().ok_or(Error::ElementNotFound("[id$=DateStart]"))?;
@@ -51,12 +67,13 @@ impl CedaeScraper {
 
         let date = NaiveDate::parse_from_str(&date_text, "%d/%m/%Y").ok();
 
-        Ok(NewsPost::new(title, url.to_string(), content_text, date))
+        Ok(NewsPost::new(NAME, title, url.to_string(), content_text, date.map(local_date)))
     }
 
-    pub fn new() -> Self {
+    pub fn new(fetcher: Arc<dyn PageFetcher>, base_url: Url) -> Self {
         Self {
-            base_url: Url::parse("https://cedae.com.br/Noticias/").unwrap(),
+            fetcher,
+            base_url,
 
             news_list_selector: Selector::parse(".lista-busca").unwrap(),
             links_selector: Selector::parse("a").unwrap(),
@@ -64,4 +81,38 @@ impl CedaeScraper {
             content_element_selector: Selector::parse("[id$=NewsBody]").unwrap(),
         }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::NaiveDate;
+    use reqwest::Url;
+
+    use crate::{news_post::{local_date, NewsPost}, scrapers::{fixture_fetcher, Scraper}};
+
+    use super::{CedaeScraper, DEFAULT_URL, NAME};
+
+    #[tokio::test]
+    async fn extracts_posts_from_fixtures() {
+        let scraper = CedaeScraper::new(fixture_fetcher("cedae"), Url::parse(DEFAULT_URL).unwrap());
+
+        let posts = scraper.get_posts().await.unwrap();
+
+        assert_eq!(posts, vec![
+            NewsPost::new(
+                NAME,
+                "Manutenção programada na ETA Guandu".to_string(),
+                "https://cedae.com.br/Noticias/detalhe/manutencao-guandu".to_string(),
+                "A CEDAE informa que fará manutenção na ETA Guandu.".to_string(),
+                NaiveDate::from_ymd_opt(2024, 10, 14).map(local_date),
+            ),
+            NewsPost::new(
+                NAME,
+                "Abastecimento normalizado".to_string(),
+                "https://cedae.com.br/Noticias/detalhe/abastecimento-normalizado".to_string(),
+                "O abastecimento foi normalizado.".to_string(),
+                None,
+            ),
+        ]);
+    }
+}
@@ -0,0 +1,196 @@
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use chrono::{NaiveDate, NaiveDateTime};
+use futures::future::try_join_all;
+use reqwest::Url;
+use scraper::{ElementRef, Html, Selector};
+
+use crate::{config::GenericScraperConfig, error::Error, fetchers::PageFetcher, news_post::{local_date, local_date_time, NewsPost, PostDate}};
+
+use super::Scraper;
+
+// NOTE: Errors name the config field whose selector did not match, since
+// the selectors themselves are only known at runtime.
+pub struct GenericHtmlScraper {
+    fetcher: Arc<dyn PageFetcher>,
+    name: String,
+    base_url: Url,
+    page_url: Option<String>,
+
+    posts_selector: Selector,
+    title_selector: Selector,
+    link_selector: Option<Selector>,
+    date_selector: Option<Selector>,
+    content_selector: Option<Selector>,
+
+    date_format: String,
+    follow_links: bool,
+    detail_content_selector: Option<Selector>,
+}
+
+struct GenericPost {
+    title: String,
+    url: Url,
+    date: Option<PostDate>,
+    content: String,
+}
+
+#[async_trait(?Send)]
+impl Scraper for GenericHtmlScraper {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    // NOTE: Without a page_url there is only the first page.
+    async fn get_page(&self, page: usize) -> Result<Vec<NewsPost>, Error> {
+        let url = match (page, &self.page_url) {
+            (0, _) => self.base_url.clone(),
+            // NOTE: page_url was already checked by Config::validate.
+            (_, Some(page_url)) => Url::parse(&page_url.replace("{page}", &(page + 1).to_string())).expect("Invalid page URL"),
+            (_, None) => return Ok(vec![]),
+        };
+
+        let data = self.fetcher.fetch(url).await?;
+        let html = Html::parse_document(&data);
+
+        let posts = html
+            .select(&self.posts_selector)
+            .map(|post_element| self.parse_post(post_element))
+            .collect::<Result<Vec<_>, Error>>()?;
+
+        let posts_futures = posts.into_iter().map(|post| async move {
+            let content = if self.follow_links {
+                self.get_detail_content(post.url.clone()).await?
+            } else {
+                post.content
+            };
+
+            Ok::<_, Error>(NewsPost::new(&self.name, post.title, post.url.to_string(), content, post.date))
+        });
+
+        try_join_all(posts_futures).await
+    }
+}
+
+impl GenericHtmlScraper {
+    fn parse_post(&self, post_element: ElementRef) -> Result<GenericPost, Error> {
+        let title_element = post_element.select(&self.title_selector).next().ok_or(Error::ElementNotFound("title_selector"))?;
+
+        // NOTE: Without a link selector the post element itself is expected
+        // to be the link, e.g. a card wrapped in an <a> tag.
+        let link_element = match &self.link_selector {
+            Some(link_selector) => post_element.select(link_selector).next().ok_or(Error::ElementNotFound("link_selector"))?,
+            None => post_element,
+        };
+        let link_str = link_element.value().attr("href").ok_or(Error::AttrNotFound("href"))?;
+
+        let date = match &self.date_selector {
+            Some(date_selector) => {
+                let date_element = post_element.select(date_selector).next().ok_or(Error::ElementNotFound("date_selector"))?;
+                let date_text = date_element.text().collect::<String>();
+
+                // NOTE: The time is only kept when date_format includes it.
+                NaiveDateTime::parse_from_str(date_text.trim(), &self.date_format)
+                    .map(local_date_time)
+                    .or_else(|_| NaiveDate::parse_from_str(date_text.trim(), &self.date_format).map(local_date))
+                    .ok()
+            },
+            None => None,
+        };
+
+        let content = match &self.content_selector {
+            Some(content_selector) => {
+                let content_element = post_element.select(content_selector).next().ok_or(Error::ElementNotFound("content_selector"))?;
+                content_element.text().collect()
+            },
+            None => String::new(),
+        };
+
+        Ok(GenericPost {
+            title: title_element.text().map(str::trim).collect(),
+            url: self.base_url.join(link_str).unwrap(),
+            date,
+            content,
+        })
+    }
+
+    async fn get_detail_content(&self, url: Url) -> Result<String, Error> {
+        let data = self.fetcher.fetch(url).await?;
+        let html = Html::parse_document(&data);
+
+        let detail_content_selector = self.detail_content_selector.as_ref().ok_or(Error::ElementNotFound("detail_content_selector"))?;
+        let content_element = html.select(detail_content_selector).next().ok_or(Error::ElementNotFound("detail_content_selector"))?;
+
+        Ok(content_element.text().collect())
+    }
+
+    // NOTE: The config is expected to have been checked by Config::validate,
+    // so URLs and selectors are known to parse.
+    pub fn new(fetcher: Arc<dyn PageFetcher>, config: &GenericScraperConfig) -> Self {
+        let parse_selector = |selector: &str| Selector::parse(selector).unwrap();
+
+        Self {
+            fetcher,
+            name: config.name.clone(),
+            base_url: Url::parse(&config.url).unwrap(),
+            page_url: config.page_url.clone(),
+
+            posts_selector: parse_selector(&config.posts_selector),
+            title_selector: parse_selector(&config.title_selector),
+            link_selector: config.link_selector.as_deref().map(parse_selector),
+            date_selector: config.date_selector.as_deref().map(parse_selector),
+            content_selector: config.content_selector.as_deref().map(parse_selector),
+
+            date_format: config.date_format.clone(),
+            follow_links: config.follow_links,
+            detail_content_selector: config.detail_content_selector.as_deref().map(parse_selector),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::NaiveDate;
+
+    use crate::{config::GenericScraperConfig, news_post::{local_date, NewsPost}, scrapers::{fixture_fetcher, Scraper}};
+
+    use super::GenericHtmlScraper;
+
+    #[tokio::test]
+    async fn extracts_posts_from_fixtures() {
+        let config: GenericScraperConfig = toml::from_str(r#"
+            name = "Prolagos"
+            url = "https://prolagos.com.br/noticias/"
+            posts_selector = "article.noticia"
+            title_selector = "h2"
+            link_selector = "a"
+            date_selector = "time"
+            date_format = "%d.%m.%Y"
+            follow_links = true
+            detail_content_selector = ".entry-content"
+        "#).unwrap();
+
+        let scraper = GenericHtmlScraper::new(fixture_fetcher("generic"), &config);
+
+        let posts = scraper.get_posts().await.unwrap();
+
+        assert_eq!(scraper.name(), "Prolagos");
+        assert_eq!(posts, vec![
+            NewsPost::new(
+                "Prolagos",
+                "Manutenção em Cabo Frio".to_string(),
+                "https://prolagos.com.br/noticias/manutencao-em-cabo-frio/".to_string(),
+                "A Prolagos fará manutenção na rede de Cabo Frio.".to_string(),
+                NaiveDate::from_ymd_opt(2024, 10, 16).map(local_date),
+            ),
+            NewsPost::new(
+                "Prolagos",
+                "Obra em São Pedro da Aldeia".to_string(),
+                "https://prolagos.com.br/noticias/obra-em-sao-pedro-da-aldeia/".to_string(),
+                "A obra começa na próxima semana.".to_string(),
+                None,
+            ),
+        ]);
+    }
+}
@@ -1,13 +1,22 @@
+use std::sync::Arc;
+
 use async_trait::async_trait;
-use chrono::{Month, NaiveDate};
+use chrono::NaiveDate;
+use futures::future::try_join_all;
 use reqwest::Url;
 use scraper::{Html, Selector};
 
-use crate::{error::Error, news_post::NewsPost};
+use crate::{error::Error, fetchers::PageFetcher, gazetteer, news_post::{local_date, NewsPost}};
+
+use super::{with_query_param, Scraper};
+
+pub const NAME: &str = "Iguá";
+pub const DEFAULT_URL: &str = "https://igua.com.br/noticias?page=1";
 
-use super::Scraper;
+const MONTHS: [&str; 12] = ["janeiro", "fevereiro", "marco", "abril", "maio", "junho", "julho", "agosto", "setembro", "outubro", "novembro", "dezembro"];
 
 pub struct IguaScraper {
+    fetcher: Arc<dyn PageFetcher>,
     base_url: Url,
 
     posts_wrapper_selector: Selector,
@@ -21,13 +30,29 @@ pub struct IguaScraper {
 
 #[async_trait(?Send)]
 impl Scraper for IguaScraper {
-    async fn get_posts(&self) -> Result<Vec<NewsPost>, Error> {
-        let data = reqwest::get(self.base_url.clone()).await?.text().await?;
+    fn name(&self) -> &str {
+        NAME
+    }
+
+    async fn get_page(&self, page: usize) -> Result<Vec<NewsPost>, Error> {
+        // NOTE: Iguá numbers its pages from 1.
+        let url = match page {
+            0 => self.base_url.clone(),
+            _ => with_query_param(&self.base_url, "page", &(page + 1).to_string()),
+        };
+
+        let data = self.fetcher.fetch(url).await?;
         let html = Html::parse_document(&data);
 
-        let posts_wrapper_element = html.select(&self.posts_wrapper_selector).next().ok_or(Error::ElementNotFound(".infinite-scroll"))?;
+        // NOTE: Pages past the last one have no list.
+        let Some(posts_wrapper_element) = html.select(&self.posts_wrapper_selector).next() else {
+            return match page {
+                0 => Err(Error::ElementNotFound(".infinite-scroll")),
+                _ => Ok(vec![]),
+            };
+        };
 
-        let mut ans = vec![];
+        let mut posts_futures = vec![];
         for post_element in posts_wrapper_element.select(&self.posts_selector) {
             let link_element = post_element.select(&self.link_selector).next().ok_or(Error::ElementNotFound("a"))?;
             let title_element = post_element.select(&self.title_selector).next().ok_or(Error::ElementNotFound("h3"))?;
@@ -39,18 +64,21 @@ impl Scraper for IguaScraper {
             let title = title_element.text().map(str::trim).collect();
             let url = self.base_url.join(url_str).unwrap();
             let date = Self::parse_date(&date_text);
-            let content = self.get_post_content(url.clone()).await?;
 
-            ans.push(NewsPost::new(title, url.to_string(), content, date));
+            posts_futures.push(async move {
+                let content = self.get_post_content(url.clone()).await?;
+
+                Ok::<_, Error>(NewsPost::new(NAME, title, url.to_string(), content, date.map(local_date)))
+            });
         }
 
-        Ok(ans)
+        try_join_all(posts_futures).await
     }
 }
 
 impl IguaScraper {
     async fn get_post_content(&self, url: Url) -> Result<String, Error> {
-        let data = reqwest::get(url).await?.text().await?.replace("<p", "\n<p");
+        let data = self.fetcher.fetch(url).await?.replace("<p", "\n<p");
         
         let html = Html::parse_document(&data);
 
@@ -59,23 +87,26 @@ impl IguaScraper {
         Ok(content_element.text().collect())
     }
 
+    // NOTE: Dates are written out in Portuguese, as in "3 de outubro de 2024".
     fn parse_date(date_text: &str) -> Option<NaiveDate> {
-        let mut fields_iter = date_text.split("de");
+        let date_text = gazetteer::normalize(date_text);
+        let fields = date_text.split_whitespace().filter(|field| *field != "de").collect::<Vec<_>>();
 
-        let day_text = fields_iter.next()?.trim();
-        let month_text = fields_iter.next()?.trim();
-        let year_text = fields_iter.next()?.trim();
+        let [day_text, month_text, year_text] = fields[..] else {
+            return None;
+        };
 
         let day = day_text.parse::<u32>().ok()?;
-        let month = month_text.parse::<Month>().ok()?;
+        let month = MONTHS.iter().position(|month| *month == month_text)? + 1;
         let year = year_text.parse::<i32>().ok()?;
 
-        NaiveDate::from_ymd_opt(year, month.number_from_month(), day)
+        NaiveDate::from_ymd_opt(year, month as u32, day)
     }
 
-    pub fn new() -> Self {
+    pub fn new(fetcher: Arc<dyn PageFetcher>, base_url: Url) -> Self {
         Self {
-            base_url: Url::parse("https://igua.com.br/noticias?page=1").unwrap(),
+            fetcher,
+            base_url,
 
             posts_wrapper_selector: Selector::parse(".infinite-scroll").unwrap(),
             posts_selector: Selector::parse(".infinite-scroll-content").unwrap(),
@@ -86,4 +117,56 @@ impl IguaScraper {
             post_content_selector: Selector::parse(".news-spotlight > div").unwrap(),
         }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::NaiveDate;
+    use reqwest::Url;
+
+    use crate::{error::Error, news_post::{local_date, NewsPost}, scrapers::{fixture_fetcher, get_pages, PageLimit, Scraper}};
+
+    use super::{IguaScraper, DEFAULT_URL, NAME};
+
+    #[tokio::test]
+    async fn extracts_posts_from_fixtures() {
+        let scraper = IguaScraper::new(fixture_fetcher("igua"), Url::parse(DEFAULT_URL).unwrap());
+
+        let posts = scraper.get_posts().await.unwrap();
+
+        assert_eq!(posts, vec![
+            NewsPost::new(
+                NAME,
+                "Interrupção no abastecimento em Jacarepaguá".to_string(),
+                "https://igua.com.br/noticias/interrupcao-no-abastecimento-em-jacarepagua".to_string(),
+                "\nA Iguá informa que haverá interrupção.\nO serviço será retomado às 18h.".to_string(),
+                NaiveDate::from_ymd_opt(2024, 10, 3).map(local_date),
+            ),
+            NewsPost::new(
+                NAME,
+                "Obras na Barra da Tijuca".to_string(),
+                "https://igua.com.br/noticias/obras-na-barra-da-tijuca".to_string(),
+                "\nAs obras começam na segunda-feira.".to_string(),
+                None,
+            ),
+        ]);
+    }
+
+    #[tokio::test]
+    async fn fails_when_a_later_page_fails() {
+        let scraper = IguaScraper::new(fixture_fetcher("igua"), Url::parse(DEFAULT_URL).unwrap());
+
+        let result = get_pages(&scraper, &PageLimit { first_page: 0, max_pages: 3, until: None }).await;
+
+        assert!(matches!(result, Err(Error::FixtureNotFound(url)) if url.contains("page=2")));
+    }
+
+    #[test]
+    fn parses_dates() {
+        assert_eq!(IguaScraper::parse_date("3 de outubro de 2024"), NaiveDate::from_ymd_opt(2024, 10, 3));
+        assert_eq!(IguaScraper::parse_date("17 de Dezembro de 2024"), NaiveDate::from_ymd_opt(2024, 12, 17));
+        assert_eq!(IguaScraper::parse_date("5 de março de 2025"), NaiveDate::from_ymd_opt(2025, 3, 5));
+        assert_eq!(IguaScraper::parse_date("3 de October de 2024"), None);
+        assert_eq!(IguaScraper::parse_date("ontem"), None);
+    }
+}
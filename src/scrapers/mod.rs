@@ -1,14 +1,256 @@
+use std::{collections::HashSet, sync::Arc};
+
 use async_trait::async_trait;
+use chrono::NaiveDate;
+use futures::future::join_all;
+use reqwest::Url;
 
+use crate::config::ScrapersConfig;
+use crate::fetchers::PageFetcher;
 use crate::news_post::NewsPost;
 use crate::error::Error;
 
+use aguas_do_rio_scraper::AguasDoRioScraper;
+use cedae_scraper::CedaeScraper;
+use generic_html_scraper::GenericHtmlScraper;
+use igua_scraper::IguaScraper;
+use rio_saneamento_scraper::RioSaneamentoScraper;
+
 pub mod cedae_scraper;
 pub mod rio_saneamento_scraper;
 pub mod igua_scraper;
 pub mod aguas_do_rio_scraper;
+pub mod generic_html_scraper;
+
+#[cfg(test)]
+pub(crate) fn fixture_fetcher(provider: &str) -> Arc<dyn PageFetcher> {
+    let directory = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures").join(provider);
+
+    Arc::new(crate::fetchers::fixture_fetcher::FixtureFetcher::new(directory).expect("Could not load fixtures"))
+}
 
 #[async_trait(?Send)]
 pub trait Scraper {
-    async fn get_posts(&self) -> Result<Vec<NewsPost>, Error>;
-}
\ No newline at end of file
+    fn name(&self) -> &str;
+
+    // NOTE: Pages are numbered from 0, the newest. Pages past the last one
+    // have no posts.
+    async fn get_page(&self, page: usize) -> Result<Vec<NewsPost>, Error>;
+
+    async fn get_posts(&self) -> Result<Vec<NewsPost>, Error> {
+        self.get_page(0).await
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PageLimit {
+    // NOTE: max_pages pages are read, starting at this one.
+    pub first_page: usize,
+    pub max_pages: usize,
+    // NOTE: Posts dated before this are left out, and no page after the
+    // first one holding such a post is read.
+    pub until: Option<NaiveDate>,
+}
+
+// NOTE: Reads several pages of a provider on every run.
+struct PagedScraper {
+    scraper: Box<dyn Scraper>,
+    limit: PageLimit,
+}
+
+#[async_trait(?Send)]
+impl Scraper for PagedScraper {
+    fn name(&self) -> &str {
+        self.scraper.name()
+    }
+
+    async fn get_page(&self, page: usize) -> Result<Vec<NewsPost>, Error> {
+        self.scraper.get_page(page).await
+    }
+
+    async fn get_posts(&self) -> Result<Vec<NewsPost>, Error> {
+        get_pages(self.scraper.as_ref(), &self.limit).await
+    }
+}
+
+// NOTE: The order of this list is also the order used to break ties when
+// delivering posts published on the same date.
+pub fn build_scrapers(fetcher: Arc<dyn PageFetcher>, config: &ScrapersConfig) -> Vec<Box<dyn Scraper>> {
+    let mut scrapers: Vec<Box<dyn Scraper>> = vec![];
+
+    if config.cedae.enabled {
+        scrapers.push(paged(Box::new(CedaeScraper::new(fetcher.clone(), config.cedae.url_or(cedae_scraper::DEFAULT_URL))), config.cedae.pages));
+    }
+
+    if config.rio_saneamento.enabled {
+        scrapers.push(paged(Box::new(RioSaneamentoScraper::new(fetcher.clone(), config.rio_saneamento.url_or(rio_saneamento_scraper::DEFAULT_URL))), config.rio_saneamento.pages));
+    }
+
+    if config.igua.enabled {
+        scrapers.push(paged(Box::new(IguaScraper::new(fetcher.clone(), config.igua.url_or(igua_scraper::DEFAULT_URL))), config.igua.pages));
+    }
+
+    if config.aguas_do_rio.enabled {
+        scrapers.push(paged(Box::new(AguasDoRioScraper::new(fetcher.clone(), config.aguas_do_rio.url_or(aguas_do_rio_scraper::DEFAULT_URL))), config.aguas_do_rio.pages));
+    }
+
+    for generic_config in config.generic.iter().filter(|generic_config| generic_config.enabled) {
+        scrapers.push(paged(Box::new(GenericHtmlScraper::new(fetcher.clone(), generic_config)), generic_config.pages));
+    }
+
+    scrapers
+}
+
+fn paged(scraper: Box<dyn Scraper>, pages: usize) -> Box<dyn Scraper> {
+    if pages <= 1 {
+        return scraper;
+    }
+
+    Box::new(PagedScraper { scraper, limit: PageLimit { first_page: 0, max_pages: pages, until: None } })
+}
+
+// NOTE: Walks the pages from the newest one until the limit is reached or a
+// page has no posts besides those already read. A post published while the
+// pages are walked pushes the others one place down, so the same post may
+// show up on two pages, and providers may ignore page numbers past the end.
+// An error on any page fails the walk, so posts are never missed silently.
+pub async fn get_pages(scraper: &dyn Scraper, limit: &PageLimit) -> Result<Vec<NewsPost>, Error> {
+    let is_too_old = |post: &NewsPost| matches!((limit.until, post.date()), (Some(until), Some(date)) if date.date_naive() < until);
+
+    let mut posts = vec![];
+    let mut post_ids = HashSet::new();
+
+    for page in limit.first_page..limit.first_page + limit.max_pages {
+        let page_posts = scraper.get_page(page).await?
+            .into_iter()
+            .filter(|post| post_ids.insert(post.id().to_string()))
+            .collect::<Vec<_>>();
+
+        if page_posts.is_empty() {
+            break;
+        }
+
+        let reached_until = page_posts.iter().any(is_too_old);
+        posts.extend(page_posts.into_iter().filter(|post| !is_too_old(post)));
+
+        if reached_until {
+            break;
+        }
+    }
+
+    Ok(posts)
+}
+
+// NOTE: Replaces the value of a query parameter, or appends it when missing,
+// keeping the other parameters in their order.
+pub(crate) fn with_query_param(url: &Url, name: &str, value: &str) -> Url {
+    let mut found = false;
+    let pairs = url
+        .query_pairs()
+        .map(|(key, current_value)| {
+            if key == name {
+                found = true;
+                (key.into_owned(), value.to_string())
+            } else {
+                (key.into_owned(), current_value.into_owned())
+            }
+        })
+        .collect::<Vec<_>>();
+
+    let mut url = url.clone();
+    url.query_pairs_mut().clear().extend_pairs(pairs);
+    if !found {
+        url.query_pairs_mut().append_pair(name, value);
+    }
+
+    url
+}
+
+pub async fn scrape_all(fetcher: Arc<dyn PageFetcher>, config: &ScrapersConfig) -> (Vec<NewsPost>, Vec<(String, Error)>) {
+    let scrapers = build_scrapers(fetcher, config);
+
+    scrape(&scrapers.iter().map(Box::as_ref).collect::<Vec<_>>()).await
+}
+
+pub async fn scrape(scrapers: &[&dyn Scraper]) -> (Vec<NewsPost>, Vec<(String, Error)>) {
+    let results = join_all(scrapers.iter().map(|scraper| scraper.get_posts())).await;
+
+    // NOTE: A failing scraper must not prevent the others from delivering
+    // their posts, so errors are collected and reported together at the end.
+    let mut failures = vec![];
+    let mut posts = vec![];
+
+    for (provider_index, (scraper, result)) in scrapers.iter().zip(results).enumerate() {
+        match result {
+            Ok(scraper_posts) => posts.extend(scraper_posts.into_iter().map(|post| (provider_index, post))),
+            Err(error) => failures.push((scraper.name().to_string(), error)),
+        }
+    }
+
+    // NOTE: Scrapers finish in any order, so posts are delivered from the
+    // oldest to the newest, with ties broken by the provider's position in
+    // the list. Undated posts are delivered last.
+    posts.sort_by_key(|(provider_index, post)| (post.date().is_none(), *post.date(), *provider_index));
+
+    let posts = posts.into_iter().map(|(_, post)| post).collect();
+
+    (posts, failures)
+}
+
+#[cfg(test)]
+mod tests {
+    use async_trait::async_trait;
+    use chrono::NaiveDate;
+    use reqwest::Url;
+
+    use crate::{error::Error, news_post::{local_date, NewsPost}};
+
+    use super::{get_pages, with_query_param, PageLimit, Scraper};
+
+    struct PagesScraper {
+        pages: Vec<Vec<(&'static str, u32)>>,
+    }
+
+    #[async_trait(?Send)]
+    impl Scraper for PagesScraper {
+        fn name(&self) -> &str {
+            "CEDAE"
+        }
+
+        async fn get_page(&self, page: usize) -> Result<Vec<NewsPost>, Error> {
+            let posts = self.pages.get(page).cloned().unwrap_or_default();
+
+            Ok(posts.into_iter().map(|(slug, day)| post(slug, day)).collect())
+        }
+    }
+
+    fn post(slug: &str, day: u32) -> NewsPost {
+        NewsPost::new("CEDAE", slug.to_string(), format!("https://cedae.com.br/{}", slug), String::new(), NaiveDate::from_ymd_opt(2024, 10, day).map(local_date))
+    }
+
+    #[tokio::test]
+    async fn walks_pages_until_the_limit() {
+        let scraper = PagesScraper {
+            pages: vec![
+                vec![("e", 15), ("d", 14)],
+                vec![("d", 14), ("c", 13)],
+                vec![("b", 12), ("a", 11)],
+            ],
+        };
+
+        let slugs = |posts: Vec<NewsPost>| posts.iter().map(|post| post.title().to_string()).collect::<Vec<_>>();
+
+        assert_eq!(slugs(get_pages(&scraper, &PageLimit { first_page: 0, max_pages: 2, until: None }).await.unwrap()), vec!["e", "d", "c"]);
+        assert_eq!(slugs(get_pages(&scraper, &PageLimit { first_page: 0, max_pages: 10, until: None }).await.unwrap()), vec!["e", "d", "c", "b", "a"]);
+        assert_eq!(slugs(get_pages(&scraper, &PageLimit { first_page: 0, max_pages: 10, until: NaiveDate::from_ymd_opt(2024, 10, 14) }).await.unwrap()), vec!["e", "d"]);
+        assert_eq!(slugs(get_pages(&scraper, &PageLimit { first_page: 1, max_pages: 10, until: None }).await.unwrap()), vec!["d", "c", "b", "a"]);
+    }
+
+    #[test]
+    fn replaces_query_params() {
+        let url = Url::parse("https://aguasdorio.com.br/ajax?id=lista&page=0&offset=0").unwrap();
+
+        assert_eq!(with_query_param(&url, "page", "2").as_str(), "https://aguasdorio.com.br/ajax?id=lista&page=2&offset=0");
+        assert_eq!(with_query_param(&url, "order", "DESC").as_str(), "https://aguasdorio.com.br/ajax?id=lista&page=0&offset=0&order=DESC");
+    }
+}
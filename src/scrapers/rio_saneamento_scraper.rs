@@ -1,12 +1,18 @@
+use std::sync::Arc;
+
 use async_trait::async_trait;
 use chrono::NaiveDate;
+use futures::future::try_join_all;
 use reqwest::Url;
 use scraper::{selectable::Selectable, Html, Selector};
 
-use crate::{error::Error, news_post::NewsPost};
+use crate::{error::Error, fetchers::PageFetcher, news_post::{local_date, NewsPost}};
 
 use super::Scraper;
 
+pub const NAME: &str = "Rio+Saneamento";
+pub const DEFAULT_URL: &str = "https://www.riomaissaneamento.com.br/noticias/";
+
 #[derive(Debug)]
 struct RioSaneamentoPost {
     title: String,
@@ -15,6 +21,7 @@ struct RioSaneamentoPost {
 }
 
 pub struct RioSaneamentoScraper {
+    fetcher: Arc<dyn PageFetcher>,
     base_url: Url,
 
     main_posts_wrapper_selector: Selector,
@@ -33,28 +40,42 @@ pub struct RioSaneamentoScraper {
 
 #[async_trait(?Send)]
 impl Scraper for RioSaneamentoScraper {
-    async fn get_posts(&self) -> Result<Vec<NewsPost>, Error> {
-        let data = reqwest::get(self.base_url.clone()).await?.text().await?;
+    fn name(&self) -> &str {
+        NAME
+    }
+
+    // NOTE: The later pages use WordPress' usual ".../page/2/" URLs and may
+    // have only one of the two lists.
+    async fn get_page(&self, page: usize) -> Result<Vec<NewsPost>, Error> {
+        let url = match page {
+            0 => self.base_url.clone(),
+            _ => self.base_url.join(&format!("page/{}/", page + 1)).unwrap(),
+        };
+
+        let data = self.fetcher.fetch(url).await?;
         let html = Html::parse_document(&data);
 
-        let main_posts = self.get_main_posts(&html)?;
-        let secondary_posts = self.get_secondary_posts(&html)?;
+        let main_posts = self.get_main_posts(&html, page == 0)?;
+        let secondary_posts = self.get_secondary_posts(&html, page == 0)?;
 
-        let mut ans = vec![];
-        for post in main_posts.into_iter().chain(secondary_posts.into_iter()) {
-            let content = self.get_post_content(post.url.clone()).await?;
-            let post = NewsPost::new(post.title, post.url.to_string(), content, post.date);
+        let posts_futures = main_posts
+            .into_iter()
+            .chain(secondary_posts)
+            .map(|post| async move {
+                let content = self.get_post_content(post.url.clone()).await?;
 
-            ans.push(post);
-        }
+                Ok::<_, Error>(NewsPost::new(NAME, post.title, post.url.to_string(), content, post.date.map(local_date)))
+            });
 
-        Ok(ans)
+        try_join_all(posts_futures).await
     }
 }
 
 impl RioSaneamentoScraper {
-    fn get_main_posts(&self, html: &Html) -> Result<Vec<RioSaneamentoPost>, Error> {
-        let main_posts_wrapper = html.select(&self.main_posts_wrapper_selector).next().ok_or(Error::ElementNotFound(".gab-newsBlockWrapper"))?;
+    fn get_main_posts(&self, html: &Html, required: bool) -> Result<Vec<RioSaneamentoPost>, Error> {
+        let Some(main_posts_wrapper) = html.select(&self.main_posts_wrapper_selector).next() else {
+            return if required { Err(Error::ElementNotFound(".gab-newsBlockWrapper")) } else { Ok(vec![]) };
+        };
 
         main_posts_wrapper
             .select(&self.main_posts_selector)
@@ -75,8 +96,10 @@ impl RioSaneamentoScraper {
             .collect()
     }
 
-    fn get_secondary_posts(&self, html: &Html) -> Result<Vec<RioSaneamentoPost>, Error> {
-        let secondary_posts_wrapper = html.select(&self.secondary_posts_wrapper_selector).next().ok_or(Error::ElementNotFound(".gab-latest-posts"))?;
+    fn get_secondary_posts(&self, html: &Html, required: bool) -> Result<Vec<RioSaneamentoPost>, Error> {
+        let Some(secondary_posts_wrapper) = html.select(&self.secondary_posts_wrapper_selector).next() else {
+            return if required { Err(Error::ElementNotFound(".gab-latest-posts")) } else { Ok(vec![]) };
+        };
 
         secondary_posts_wrapper
             .select(&self.secondary_posts_selector)
@@ -98,7 +121,7 @@ impl RioSaneamentoScraper {
     }
 
     async fn get_post_content(&self, url: Url) -> Result<String, Error> {
-        let data = reqwest::get(url).await?.text().await?;
+        let data = self.fetcher.fetch(url).await?;
         let html = Html::parse_document(&data);
 
         let content_element = html.select(&self.post_content_selector).next().ok_or(Error::ElementNotFound(".content-single__content"))?;
@@ -106,9 +129,10 @@ impl RioSaneamentoScraper {
         Ok(content_element.text().collect())
     }
 
-    pub fn new() -> Self {
+    pub fn new(fetcher: Arc<dyn PageFetcher>, base_url: Url) -> Self {
         Self {
-            base_url: Url::parse("https://www.riomaissaneamento.com.br/noticias/").unwrap(),
+            fetcher,
+            base_url,
 
             main_posts_wrapper_selector: Selector::parse(".gab-newsBlockWrapper").unwrap(),
             main_posts_selector: Selector::parse("a").unwrap(),
@@ -123,4 +147,38 @@ impl RioSaneamentoScraper {
             post_content_selector: Selector::parse(".content-single__content").unwrap(),
         }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::NaiveDate;
+    use reqwest::Url;
+
+    use crate::{news_post::{local_date, NewsPost}, scrapers::{fixture_fetcher, Scraper}};
+
+    use super::{RioSaneamentoScraper, DEFAULT_URL, NAME};
+
+    #[tokio::test]
+    async fn extracts_posts_from_fixtures() {
+        let scraper = RioSaneamentoScraper::new(fixture_fetcher("rio_saneamento"), Url::parse(DEFAULT_URL).unwrap());
+
+        let posts = scraper.get_posts().await.unwrap();
+
+        assert_eq!(posts, vec![
+            NewsPost::new(
+                NAME,
+                "Reparo emergencial em Santa Cruz".to_string(),
+                "https://www.riomaissaneamento.com.br/noticias/reparo-emergencial-em-santa-cruz/".to_string(),
+                "O reparo deve ser concluído até as 22h.".to_string(),
+                NaiveDate::from_ymd_opt(2024, 10, 5).map(local_date),
+            ),
+            NewsPost::new(
+                NAME,
+                "Limpeza de reservatórios".to_string(),
+                "https://www.riomaissaneamento.com.br/noticias/limpeza-de-reservatorios/".to_string(),
+                "Os reservatórios serão limpos nesta semana.".to_string(),
+                NaiveDate::from_ymd_opt(2024, 9, 12).map(local_date),
+            ),
+        ]);
+    }
+}
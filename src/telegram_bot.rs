@@ -1,125 +1,514 @@
 use std::time::Duration;
 
-use telegram_bot_api::{bot::{self, BotApi}, methods::SendMessage};
+use lazy_static::lazy_static;
+use regex::Regex;
+use serde::{de::DeserializeOwned, Serialize};
+use telegram_bot_api::{bot::{self, APIResponseError, BotApi}, methods::{GetUpdates, Methods, SendMessage}, types::{ChatId, Message, Update}};
 use tokio::time::sleep;
 
-use crate::error::Error;
+use crate::{error::Error, rate_limiter::RateLimiter};
+
+lazy_static! {
+    static ref HTML_TAG_RE: Regex = Regex::new(r"<[^>]*>").unwrap();
+}
 
 pub enum TelegramParseMode {
-    Markdown,
+    Html,
     PlainText
 }
 
 impl TelegramParseMode {
     fn get_value(&self) -> Option<String> {
         match self {
-            TelegramParseMode::Markdown => Some("Markdown".to_string()),
+            TelegramParseMode::Html => Some("HTML".to_string()),
             TelegramParseMode::PlainText => None,
         }
     }
 }
 
+pub struct TelegramBotConfig {
+    pub api_key: String,
+
+    pub per_chat_interval: Duration,
+    pub global_interval: Duration,
+
+    pub max_retries: u32,
+    pub retry_base_delay: Duration,
+}
+
+impl Default for TelegramBotConfig {
+    fn default() -> Self {
+        Self {
+            api_key: String::new(),
+
+            per_chat_interval: Duration::from_secs(3),
+            global_interval: Duration::from_secs(1) / 30,
+
+            max_retries: 5,
+            retry_base_delay: Duration::from_secs(1),
+        }
+    }
+}
+
 pub struct TelegramBot {
-    bot_api: BotApi
+    bot_api: BotApi,
+    rate_limiter: RateLimiter,
+
+    max_retries: u32,
+    retry_base_delay: Duration,
 }
 
 impl TelegramBot {
-    pub async fn new(token: String) -> Self {
-        let bot_api = bot::BotApi::new(token, None).await.expect("Failed to login");
+    pub async fn new(config: TelegramBotConfig) -> Result<Self, Error> {
+        let bot_api = bot::BotApi::new(config.api_key, None).await?;
 
-        Self { 
-            bot_api
+        Ok(Self {
+            bot_api,
+            rate_limiter: RateLimiter::new(config.per_chat_interval, config.global_interval),
+
+            max_retries: config.max_retries,
+            retry_base_delay: config.retry_base_delay,
+        })
+    }
+
+    // NOTE: Returns the id of every part the message was split into.
+    pub async fn send_message(&self, msg: &str, chat_id: &str, parse_mode: TelegramParseMode) -> Result<Vec<i64>, Error> {
+        let mut message_ids = vec![];
+
+        for text in split_message(msg, &parse_mode) {
+            message_ids.push(self.send_part(text, chat_id, &parse_mode).await?);
         }
+
+        Ok(message_ids)
     }
 
-    pub async fn send_message(&self, msg: &str, chat_id: &str, parse_mode: TelegramParseMode) -> Result<(), Error> {
-        const MESSAGES_INTERVAL: u64 = 3000;
+    // NOTE: Replaces the text of a message previously sent in parts. The new
+    // text may need more or fewer parts than before, so missing parts are
+    // sent after the existing ones and leftover parts are deleted. Returns
+    // the ids of the parts now making up the message.
+    pub async fn edit_message(&self, msg: &str, chat_id: &str, message_ids: &[i64], parse_mode: TelegramParseMode) -> Result<Vec<i64>, Error> {
+        let texts = split_message(msg, &parse_mode);
+        let mut new_message_ids = vec![];
+
+        for (index, text) in texts.into_iter().enumerate() {
+            match message_ids.get(index) {
+                Some(&message_id) => {
+                    self.edit_part(text, chat_id, message_id, &parse_mode).await?;
+                    new_message_ids.push(message_id);
+                },
+                None => new_message_ids.push(self.send_part(text, chat_id, &parse_mode).await?),
+            }
+        }
+
+        // NOTE: Bots can only delete their messages for 48 hours, unless they
+        // are admins, so a leftover part that cannot be deleted is replaced by
+        // a marker and kept, to be reused by later edits.
+        let mut kept_message_ids = vec![];
+        for &message_id in message_ids.iter().skip(new_message_ids.len()) {
+            let request = DeleteMessage { chat_id: ChatId::StringType(chat_id.to_string()), message_id };
+            if let Err(error) = self.call::<_, bool>(chat_id, request).await {
+                eprintln!("Could not delete message {} in chat {}: {}", message_id, chat_id, error);
 
-        let requests = MessageSplitIterator::new(msg)
-            .map(|(msg, should_insert_ellipsis)| {
-                let mut text = msg.to_string();
-                if should_insert_ellipsis {
-                    text.push_str(" […]");
+                if let Err(error) = self.edit_part(LEFTOVER_PART_TEXT.to_string(), chat_id, message_id, &TelegramParseMode::PlainText).await {
+                    eprintln!("Could not edit message {} in chat {}: {}", message_id, chat_id, error);
                 }
 
-                let mut request = SendMessage::new(telegram_bot_api::types::ChatId::StringType(chat_id.to_string()), text);
-                request.parse_mode = parse_mode.get_value();
+                kept_message_ids.push(message_id);
+            }
+        }
+        new_message_ids.extend(kept_message_ids);
+
+        Ok(new_message_ids)
+    }
 
-                request
-            })
-            .collect::<Vec<_>>();
+    pub async fn get_updates(&self, offset: Option<i64>, timeout: Duration) -> Result<Vec<Update>, Error> {
+        let mut request = GetUpdates::new();
+        request.offset = offset;
+        request.timeout = Some(timeout.as_secs() as i64);
+        request.allowed_updates = Some(vec!["message".to_string()]);
+
+        let updates = self.bot_api.get_updates(request).await?;
+
+        Ok(updates)
+    }
 
-        // TODO: Make a stream from the Iterator and avoid this Vec.
-        for request in requests {
-            self.bot_api.send_message(request).await?;
-            sleep(Duration::from_millis(MESSAGES_INTERVAL)).await;
+    // NOTE: Should Telegram still reject the formatting, the part is sent
+    // again as plain text rather than not at all.
+    pub async fn send_part(&self, text: String, chat_id: &str, parse_mode: &TelegramParseMode) -> Result<i64, Error> {
+        let mut request = SendMessage::new(ChatId::StringType(chat_id.to_string()), text.clone());
+        request.parse_mode = parse_mode.get_value();
+
+        let message: Message = match self.call(chat_id, request).await {
+            Err(error) if parse_mode.get_value().is_some() && is_parse_error(&error) => {
+                let request = SendMessage::new(ChatId::StringType(chat_id.to_string()), html_to_plain_text(&text));
+                self.call(chat_id, request).await?
+            },
+            result => result?,
+        };
+
+        Ok(message.message_id)
+    }
+
+    async fn edit_part(&self, text: String, chat_id: &str, message_id: i64, parse_mode: &TelegramParseMode) -> Result<(), Error> {
+        let request = EditMessageText {
+            chat_id: ChatId::StringType(chat_id.to_string()),
+            message_id,
+            text: text.clone(),
+            parse_mode: parse_mode.get_value(),
+        };
+
+        let result = match self.call::<_, serde_json::Value>(chat_id, request).await {
+            Err(error) if parse_mode.get_value().is_some() && is_parse_error(&error) => {
+                let request = EditMessageText {
+                    chat_id: ChatId::StringType(chat_id.to_string()),
+                    message_id,
+                    text: html_to_plain_text(&text),
+                    parse_mode: None,
+                };
+
+                self.call::<_, serde_json::Value>(chat_id, request).await
+            },
+            result => result,
+        };
+
+        // NOTE: Telegram refuses edits that leave a part unchanged, which
+        // happens whenever only a later part of a long message was edited.
+        let result = match result {
+            Err(error) if api_error_message(&error).is_some_and(|message| message.contains("message is not modified")) => Ok(()),
+            result => result.map(|_| ()),
+        };
+
+        result.map_err(|e| e.into())
+    }
+
+    // NOTE: Waits for the chat's rate limit before every attempt. Network
+    // and server errors are retried with exponential backoff, and rate
+    // limited requests after the retry_after Telegram asks for.
+    async fn call<T, R>(&self, chat_id: &str, request: T) -> Result<R, APIResponseError>
+    where
+        T: Methods + Clone,
+        R: DeserializeOwned,
+    {
+        let mut attempts = 0;
+
+        loop {
+            attempts += 1;
+
+            self.rate_limiter.wait(chat_id).await;
+
+            let error = match self.bot_api.send(request.clone()).await {
+                Ok(response) => return Ok(response),
+                Err(error) => error,
+            };
+
+            let backoff = self.retry_base_delay * 2u32.pow(attempts - 1);
+            let delay = match error.downcast_ref::<bot::Error>() {
+                Some(api_error) if api_error.code == 429 => api_error.parameters
+                    .as_ref()
+                    .and_then(|parameters| parameters.retry_after)
+                    .map(|retry_after| Duration::from_secs(retry_after.max(0) as u64))
+                    .unwrap_or(backoff),
+                Some(api_error) if api_error.code >= 500 => backoff,
+                Some(_) => return Err(error),
+                // NOTE: Anything but an API error means the request did not
+                // get a proper response.
+                None => backoff,
+            };
+
+            if attempts > self.max_retries {
+                return Err(error);
+            }
+
+            sleep(delay).await;
         }
+    }
+}
+
+// NOTE: The telegram-bot-api crate does not implement these methods.
+#[derive(Serialize, Clone)]
+struct EditMessageText {
+    chat_id: ChatId,
+    message_id: i64,
+    text: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    parse_mode: Option<String>,
+}
+
+impl Methods for EditMessageText {
+    fn endpoint(&self) -> String {
+        "editMessageText".to_string()
+    }
+}
+
+#[derive(Serialize, Clone)]
+struct DeleteMessage {
+    chat_id: ChatId,
+    message_id: i64,
+}
+
+impl Methods for DeleteMessage {
+    fn endpoint(&self) -> String {
+        "deleteMessage".to_string()
+    }
+}
+
+pub fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+fn html_to_plain_text(html: &str) -> String {
+    HTML_TAG_RE.replace_all(html, "").replace("&lt;", "<").replace("&gt;", ">").replace("&quot;", "\"").replace("&amp;", "&")
+}
+
+// NOTE: bot::Error's Display implementation never returns, so only its
+// fields are ever read.
+fn api_error_message(error: &APIResponseError) -> Option<&str> {
+    error.downcast_ref::<bot::Error>().map(|error| error.message.as_str())
+}
+
+fn is_parse_error(error: &APIResponseError) -> bool {
+    api_error_message(error).is_some_and(|message| message.contains("can't parse entities"))
+}
+
+// NOTE: Telegram's limit is 4096 UTF-16 code units of text once the
+// markup is parsed. Some room is left for the part numbers.
+const MESSAGE_MAX_LENGTH: usize = 4096;
+const PART_NUMBER_RESERVED_LENGTH: usize = 16;
+// NOTE: Replaces the parts left over when an edited post got shorter and
+// they could not be deleted.
+const LEFTOVER_PART_TEXT: &str = "(continua acima)";
+
+pub fn split_message(msg: &str, parse_mode: &TelegramParseMode) -> Vec<String> {
+    let is_html = matches!(parse_mode, TelegramParseMode::Html);
+    let parts = MessageSplitter::new(MESSAGE_MAX_LENGTH - PART_NUMBER_RESERVED_LENGTH).split(msg.trim(), is_html);
+
+    if parts.len() <= 1 {
+        return parts;
+    }
+
+    let parts_count = parts.len();
+    parts
+        .into_iter()
+        .enumerate()
+        .map(|(index, part)| format!("{}\n\n({}/{})", part, index + 1, parts_count))
+        .collect()
+}
+
+enum Token<'a> {
+    OpenTag { name: &'a str, raw: &'a str },
+    CloseTag { name: &'a str },
+    // NOTE: A single character or HTML entity, with its length as counted
+    // by Telegram.
+    Text { raw: &'a str, length: usize },
+}
+
+fn tokenize(msg: &str, is_html: bool) -> Vec<Token<'_>> {
+    let mut tokens = vec![];
+    let mut rest = msg;
+
+    while let Some(c) = rest.chars().next() {
+        let (token, token_len) = match c {
+            '<' if is_html && rest.contains('>') => {
+                let end = rest.find('>').unwrap() + 1;
+                let raw = &rest[..end];
+                let name = raw
+                    .trim_start_matches(['<', '/'])
+                    .split(|c: char| c.is_whitespace() || c == '>')
+                    .next()
+                    .unwrap_or_default();
+
+                if raw.starts_with("</") {
+                    (Token::CloseTag { name }, end)
+                } else {
+                    (Token::OpenTag { name, raw }, end)
+                }
+            },
+            '&' if is_html && rest.find(';').is_some_and(|end| end <= 8) => {
+                let end = rest.find(';').unwrap() + 1;
+                (Token::Text { raw: &rest[..end], length: 1 }, end)
+            },
+            _ => (Token::Text { raw: &rest[..c.len_utf8()], length: c.len_utf16() }, c.len_utf8()),
+        };
 
-        Ok(())
+        tokens.push(token);
+        rest = &rest[token_len..];
     }
+
+    tokens
+}
+
+#[derive(Clone)]
+struct OpenTag {
+    name: String,
+    raw: String,
 }
 
-struct MessageSplitIterator<'a> {
-    msg: &'a str,
+struct SplitPoint {
+    index: usize,
+    length: usize,
+    open_tags: Vec<OpenTag>,
 }
 
-impl<'a> Iterator for MessageSplitIterator<'a> {
-    type Item = (&'a str, bool);
+// NOTE: Splits a message at a paragraph boundary when possible, then at a
+// word boundary, and only then at the last character that fits. Tags open
+// at the split point are closed at the end of the part and opened again at
+// the start of the next one, so formatting is never broken.
+struct MessageSplitter {
+    max_length: usize,
+
+    parts: Vec<String>,
+    part: String,
+    length: usize,
+    open_tags: Vec<OpenTag>,
 
-    fn next(&mut self) -> Option<Self::Item> {
-        // NOTE: Telegram limit is 4096 characters. We are leaving some room at the end 
-        // so we can add ellipsis message.
-        const MESSAGE_MAX_SIZE: usize = 4000;
+    paragraph_break: Option<SplitPoint>,
+    word_break: Option<SplitPoint>,
+}
+
+impl MessageSplitter {
+    fn new(max_length: usize) -> Self {
+        Self {
+            max_length,
 
-        if self.msg.len() == 0 {
-            return None;
+            parts: vec![],
+            part: String::new(),
+            length: 0,
+            open_tags: vec![],
+
+            paragraph_break: None,
+            word_break: None,
         }
+    }
 
-        if self.msg.len() <= MESSAGE_MAX_SIZE {
-            let ans = Some((self.msg, false));
-            self.msg = "";
-            return ans;
+    fn split(mut self, msg: &str, is_html: bool) -> Vec<String> {
+        for token in tokenize(msg, is_html) {
+            match token {
+                Token::OpenTag { name, raw } => {
+                    self.open_tags.push(OpenTag { name: name.to_string(), raw: raw.to_string() });
+                    self.part.push_str(raw);
+                },
+                Token::CloseTag { name } => {
+                    if let Some(index) = self.open_tags.iter().rposition(|tag| tag.name == name) {
+                        self.open_tags.remove(index);
+                    }
+                    self.part.push_str(&format!("</{}>", name));
+                },
+                Token::Text { raw, length } => self.push_text(raw, length),
+            }
         }
 
-        // NOTE: First we try to break the message at a paragraph boundary by
-        // searching for a newline character. If we can't find a suitable 
-        // split point, we try to break the message at a word boundary by 
-        // searching for a space characters. If no neither case is found, 
-        // we split the message at the last possible character.
+        if self.length > 0 {
+            self.parts.push(self.part.trim().to_string());
+        }
 
-        let max_slice_boundary = find_floor_char_boundary(self.msg, MESSAGE_MAX_SIZE);
-        let max_slice = &self.msg[..max_slice_boundary];
+        self.parts
+    }
 
-        if let Some(index) = max_slice.rfind('\n') {
-            let ans = Some((self.msg[..index].trim(), true));
-            self.msg = &self.msg[(index+1)..].trim();
-            return ans;
+    fn push_text(&mut self, raw: &str, length: usize) {
+        if self.length + length > self.max_length {
+            self.break_part();
         }
 
-        if let Some(index) = max_slice.rfind(' ') {
-            let ans = Some((self.msg[..index].trim(), true));
-            self.msg = &self.msg[(index+1)..].trim();
-            return ans;
+        if self.length > 0 && (raw == "\n" || raw == " ") {
+            let split_point = SplitPoint { index: self.part.len(), length: self.length, open_tags: self.open_tags.clone() };
+
+            if raw == "\n" {
+                self.paragraph_break = Some(split_point);
+            } else {
+                self.word_break = Some(split_point);
+            }
         }
 
-        self.msg = &self.msg[max_slice_boundary..];
-        
-        Some((max_slice, true))
+        self.part.push_str(raw);
+        self.length += length;
     }
-}
 
-impl<'a> MessageSplitIterator<'a> {
-    fn new(msg: &'a str) -> Self {
-        Self {
-            msg: msg.trim()
+    fn break_part(&mut self) {
+        // NOTE: A paragraph break too close to the start of the part would
+        // leave it mostly empty, so a word break is preferred then.
+        let split_point = match (self.paragraph_break.take(), self.word_break.take()) {
+            (Some(paragraph_break), _) if paragraph_break.length >= self.max_length / 2 => Some(paragraph_break),
+            (paragraph_break, word_break) => word_break.or(paragraph_break),
+        };
+
+        let (head, tail, tail_length, open_tags) = match split_point {
+            // NOTE: The separator itself is dropped.
+            Some(split_point) => (
+                &self.part[..split_point.index],
+                &self.part[(split_point.index + 1)..],
+                self.length - split_point.length - 1,
+                split_point.open_tags,
+            ),
+            None => (self.part.as_str(), "", 0, self.open_tags.clone()),
+        };
+
+        let mut finished_part = head.trim_end().to_string();
+        for tag in open_tags.iter().rev() {
+            finished_part.push_str(&format!("</{}>", tag.name));
         }
+
+        let trimmed_tail = tail.trim_start();
+        let trimmed_length = tail[..(tail.len() - trimmed_tail.len())].chars().map(char::len_utf16).sum::<usize>();
+
+        let mut next_part = open_tags.iter().map(|tag| tag.raw.as_str()).collect::<String>();
+        next_part.push_str(trimmed_tail);
+
+        self.parts.push(finished_part);
+        self.part = next_part;
+        self.length = tail_length - trimmed_length;
     }
 }
 
-fn find_floor_char_boundary(s: &str, mut index: usize) -> usize {
-    while !s.is_char_boundary(index) {
-        index -= 1;
+#[cfg(test)]
+mod tests {
+    use super::{split_message, TelegramParseMode, MESSAGE_MAX_LENGTH};
+
+    fn telegram_length(part: &str) -> usize {
+        super::html_to_plain_text(part).encode_utf16().count()
     }
 
-    return index;
-}
\ No newline at end of file
+    #[test]
+    fn keeps_short_messages_whole() {
+        assert_eq!(split_message("  <b>Obra</b> em Bangu\n", &TelegramParseMode::Html), vec!["<b>Obra</b> em Bangu"]);
+    }
+
+    #[test]
+    fn reopens_formatting_across_parts() {
+        let paragraph = "palavra ".repeat(300);
+        let msg = format!("<a href=\"https://cedae.com.br\">Título</a>\n\n<b>{}</b>", paragraph.repeat(2).trim_end());
+
+        let parts = split_message(&msg, &TelegramParseMode::Html);
+
+        assert_eq!(parts.len(), 2);
+        assert!(parts[0].starts_with("<a href=\"https://cedae.com.br\">Título</a>\n\n<b>palavra"));
+        assert!(parts[0].ends_with("palavra</b>\n\n(1/2)"));
+        assert!(parts[1].starts_with("<b>palavra"));
+        assert!(parts[1].ends_with("palavra</b>\n\n(2/2)"));
+        assert!(parts.iter().all(|part| telegram_length(part) <= MESSAGE_MAX_LENGTH));
+    }
+
+    #[test]
+    fn counts_utf16_code_units() {
+        // NOTE: Each emoji is 4 bytes but only 2 UTF-16 code units, and each
+        // entity counts as a single character.
+        let msg = "💧 &amp; ".repeat(1200);
+
+        let parts = split_message(&msg, &TelegramParseMode::Html);
+
+        assert_eq!(parts.len(), 2);
+        assert!(parts.iter().all(|part| telegram_length(part) <= MESSAGE_MAX_LENGTH));
+        assert!(parts[0].ends_with("&amp;\n\n(1/2)"));
+    }
+
+    #[test]
+    fn does_not_parse_plain_text() {
+        let msg = format!("{}<b>", "x".repeat(5000));
+
+        let parts = split_message(&msg, &TelegramParseMode::PlainText);
+
+        assert_eq!(parts.len(), 2);
+        assert!(parts[1].starts_with('x'));
+        assert!(parts[1].contains("<b>"));
+        assert!(parts.iter().all(|part| part.encode_utf16().count() <= MESSAGE_MAX_LENGTH));
+    }
+}